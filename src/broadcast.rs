@@ -0,0 +1,533 @@
+//! Fan-out of the simulation events: every drone and node reports on its own
+//! channel, a single thread reads them all and hands a copy of each event to
+//! every subscriber, then forwards the original to the shared event channels
+//! returned by `get_drones_event_receiver` and `get_nodes_event_receiver`.
+
+use crate::control::NetworkHandle;
+use crate::fault::FaultInjected;
+use crate::shortcut::ShortcutEvent;
+use crate::supervisor::{NodeCrashed, NodeRestartFailed, NodeRestarted};
+use common::types::{Event, NodeEvent, WebEvent};
+use crossbeam::channel::{Receiver, Select, Sender, unbounded};
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Instant;
+use wg_internal::controller::DroneEvent;
+use wg_internal::network::NodeId;
+
+/// An event of the simulation with the node that emitted it, shared by every
+/// subscriber
+#[derive(Debug, Clone)]
+pub enum SimulationEvent {
+    Drone(NodeId, DroneEvent),
+    Node(NodeId, Arc<dyn Event>),
+}
+
+impl SimulationEvent {
+    /// The drone or node that emitted the event
+    #[must_use]
+    pub fn source(&self) -> NodeId {
+        match self {
+            SimulationEvent::Drone(id, _) | SimulationEvent::Node(id, _) => *id,
+        }
+    }
+
+    /// Name of the event variant, e.g. `PacketDropped` or `FilesLists`, or of
+    /// the event type for the crate's own events such as `FaultInjected`
+    #[must_use]
+    pub fn kind(&self) -> String {
+        match self {
+            SimulationEvent::Drone(_, event) => match event {
+                DroneEvent::PacketSent(_) => "PacketSent",
+                DroneEvent::PacketDropped(_) => "PacketDropped",
+                DroneEvent::ControllerShortcut(_) => "ControllerShortcut",
+            }
+            .to_string(),
+            SimulationEvent::Node(_, event) => node_event_kind(event.as_ref()),
+        }
+    }
+
+    /// The node event as its concrete type, e.g. `NodeEvent` or `WebEvent`
+    #[must_use]
+    pub fn node_event<T: Any>(&self) -> Option<&T> {
+        match self {
+            SimulationEvent::Node(_, event) => {
+                let event: &dyn Any = event.as_ref();
+                event.downcast_ref()
+            }
+            SimulationEvent::Drone(..) => None,
+        }
+    }
+
+    #[must_use]
+    pub fn drone_event(&self) -> Option<&DroneEvent> {
+        match self {
+            SimulationEvent::Drone(_, event) => Some(event),
+            SimulationEvent::Node(..) => None,
+        }
+    }
+}
+
+/// A node event of a type the broadcaster cannot copy: the shared node event
+/// channel gets the original and the subscribers get it in its `Debug` form,
+/// with the kind of the original
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpaqueEvent(pub String);
+
+impl Event for OpaqueEvent {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Which events a subscriber receives, all of them by default
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    nodes: Option<HashSet<NodeId>>,
+    kinds: Option<HashSet<String>>,
+}
+
+impl EventFilter {
+    #[must_use]
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Only the events emitted by these drones and nodes
+    #[must_use]
+    pub fn with_nodes(mut self, ids: impl IntoIterator<Item = NodeId>) -> Self {
+        self.nodes = Some(ids.into_iter().collect());
+        self
+    }
+
+    /// Only the events of these kinds, see [`SimulationEvent::kind`]
+    #[must_use]
+    pub fn with_kinds<'a>(mut self, kinds: impl IntoIterator<Item = &'a str>) -> Self {
+        self.kinds = Some(kinds.into_iter().map(String::from).collect());
+        self
+    }
+
+    #[must_use]
+    pub fn matches(&self, event: &SimulationEvent) -> bool {
+        self.nodes
+            .as_ref()
+            .is_none_or(|nodes| nodes.contains(&event.source()))
+            && self
+                .kinds
+                .as_ref()
+                .is_none_or(|kinds| kinds.contains(&event.kind()))
+    }
+}
+
+struct Subscriber {
+    filter: EventFilter,
+    sender: Sender<SimulationEvent>,
+}
+
+/// The subscribers of the events, shared with the thread that publishes them
+#[derive(Clone)]
+pub(crate) struct Broadcaster {
+    // `None` once nothing will be published anymore
+    subscribers: Arc<Mutex<Option<Vec<Subscriber>>>>,
+    // when each drone or node emitted its last event
+    last_events: Arc<Mutex<HashMap<NodeId, Instant>>>,
+}
+
+impl Broadcaster {
+    pub(crate) fn new() -> Self {
+        Self {
+            subscribers: Arc::new(Mutex::new(Some(Vec::new()))),
+            last_events: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// When the drone or node `id` emitted its last event, `None` if it never did
+    pub(crate) fn last_event(&self, id: NodeId) -> Option<Instant> {
+        self.last_events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&id)
+            .copied()
+    }
+
+    fn record(&self, id: NodeId) {
+        self.last_events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, Instant::now());
+    }
+
+    /// A receiver of every event published from now on that matches `filter`,
+    /// disconnected when the publisher stops
+    pub(crate) fn subscribe(&self, filter: EventFilter) -> Receiver<SimulationEvent> {
+        let (sender, receiver) = unbounded();
+        if let Some(subscribers) = self
+            .subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+        {
+            subscribers.push(Subscriber { filter, sender });
+        }
+        receiver
+    }
+
+    pub(crate) fn has_subscribers(&self) -> bool {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .is_some_and(|subscribers| !subscribers.is_empty())
+    }
+
+    pub(crate) fn publish(&self, event: &SimulationEvent) {
+        if let Some(subscribers) = self
+            .subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+        {
+            // subscribers that dropped their receiver are forgotten
+            subscribers.retain(|subscriber| {
+                !subscriber.filter.matches(event) || subscriber.sender.send(event.clone()).is_ok()
+            });
+        }
+    }
+
+    /// Disconnects every subscriber
+    pub(crate) fn close(&self) {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+    }
+}
+
+/// The event channel of every drone and node
+#[derive(Default)]
+pub(crate) struct EventSources {
+    pub(crate) drones: Vec<(NodeId, Receiver<DroneEvent>)>,
+    pub(crate) nodes: Vec<(NodeId, Receiver<Box<dyn Event>>)>,
+}
+
+impl EventSources {
+    fn is_empty(&self) -> bool {
+        self.drones.is_empty() && self.nodes.is_empty()
+    }
+}
+
+// what the broadcaster thread read from the sources
+enum Received {
+    Drone(NodeId, DroneEvent),
+    Node(NodeId, Box<dyn Event>),
+    // the source at this index is gone
+    Disconnected(usize),
+}
+
+/// Publishes the events of `sources` until every drone and node is gone.
+/// With `shortcuts`, the packets of `DroneEvent::ControllerShortcut` are
+/// delivered and reported as a [`ShortcutEvent`] node event instead
+pub(crate) fn spawn_broadcaster(
+    mut sources: EventSources,
+    broadcaster: Broadcaster,
+    drone_events: Sender<DroneEvent>,
+    node_events: Sender<Box<dyn Event>>,
+    shortcuts: Option<NetworkHandle>,
+) -> JoinHandle<()> {
+    let watch = shortcuts.clone();
+    let run = move || {
+        while !sources.is_empty() {
+            let received = {
+                let mut select = Select::new();
+                for (_, receiver) in &sources.drones {
+                    select.recv(receiver);
+                }
+                for (_, receiver) in &sources.nodes {
+                    select.recv(receiver);
+                }
+                let operation = select.select();
+                let index = operation.index();
+                if let Some((id, receiver)) = sources.drones.get(index) {
+                    operation
+                        .recv(receiver)
+                        .map_or(Received::Disconnected(index), |event| {
+                            Received::Drone(*id, event)
+                        })
+                } else {
+                    let (id, receiver) = &sources.nodes[index - sources.drones.len()];
+                    operation
+                        .recv(receiver)
+                        .map_or(Received::Disconnected(index), |event| {
+                            Received::Node(*id, event)
+                        })
+                }
+            };
+            if let Received::Drone(id, _) | Received::Node(id, _) = &received {
+                broadcaster.record(*id);
+            }
+            match received {
+                Received::Drone(id, DroneEvent::ControllerShortcut(packet))
+                    if let Some(network) = &shortcuts =>
+                {
+                    let report = network.deliver_shortcut(id, packet);
+                    broadcaster.publish(&SimulationEvent::Node(id, Arc::new(report.clone())));
+                    let _ = node_events.send(Box::new(report));
+                }
+                Received::Drone(id, event) => {
+                    if broadcaster.has_subscribers() {
+                        broadcaster.publish(&SimulationEvent::Drone(id, event.clone()));
+                    }
+                    let _ = drone_events.send(event);
+                }
+                Received::Node(id, event) if broadcaster.has_subscribers() => {
+                    let copy = copy(event.as_ref());
+                    broadcaster.publish(&SimulationEvent::Node(id, Arc::from(copy)));
+                    let _ = node_events.send(event);
+                }
+                Received::Node(_, event) => {
+                    let _ = node_events.send(event);
+                }
+                Received::Disconnected(index) if index < sources.drones.len() => {
+                    sources.drones.remove(index);
+                }
+                Received::Disconnected(index) => {
+                    sources.nodes.remove(index - sources.drones.len());
+                }
+            }
+        }
+        broadcaster.close();
+    };
+    match watch {
+        Some(network) => std::thread::spawn(network.watched_shortcuts(run)),
+        None => std::thread::spawn(run),
+    }
+}
+
+/// The kinds [`SimulationEvent::kind`] gives the events of this crate and of
+/// `common`, for the names a scenario or a filter can check up front
+pub(crate) const EVENT_KINDS: &[&str] = &[
+    "PacketSent",
+    "PacketDropped",
+    "ControllerShortcut",
+    "FloodStarted",
+    "MessageSent",
+    "MessageReceived",
+    "ServerTypeQueried",
+    "FileOperationError",
+    "TextFileAdded",
+    "FilesLists",
+    "Delivered",
+    "Undeliverable",
+    "NodeCrashed",
+    "NodeRestarted",
+    "NodeRestartFailed",
+    "FaultInjected",
+];
+
+fn node_event_kind(event: &dyn Event) -> String {
+    let any: &dyn Any = event;
+    let kind = if let Some(event) = any.downcast_ref::<NodeEvent>() {
+        match event {
+            NodeEvent::PacketSent(_) => "PacketSent",
+            NodeEvent::FloodStarted(..) => "FloodStarted",
+            NodeEvent::MessageSent { .. } => "MessageSent",
+            NodeEvent::MessageReceived { .. } => "MessageReceived",
+            NodeEvent::ServerTypeQueried { .. } => "ServerTypeQueried",
+        }
+    } else if let Some(event) = any.downcast_ref::<WebEvent>() {
+        match event {
+            WebEvent::FileOperationError { .. } => "FileOperationError",
+            WebEvent::TextFileAdded { .. } => "TextFileAdded",
+            WebEvent::FilesLists { .. } => "FilesLists",
+        }
+    } else if let Some(event) = any.downcast_ref::<ShortcutEvent>() {
+        match event {
+            ShortcutEvent::Delivered { .. } => "Delivered",
+            ShortcutEvent::Undeliverable { .. } => "Undeliverable",
+        }
+    } else if any.is::<NodeCrashed>() {
+        "NodeCrashed"
+    } else if any.is::<NodeRestarted>() {
+        "NodeRestarted"
+    } else if any.is::<NodeRestartFailed>() {
+        "NodeRestartFailed"
+    } else if any.is::<FaultInjected>() {
+        "FaultInjected"
+    } else {
+        // a type this crate does not know, named by its `Debug` form
+        let debug = match any.downcast_ref::<OpaqueEvent>() {
+            Some(OpaqueEvent(debug)) => debug.clone(),
+            None => format!("{event:?}"),
+        };
+        return debug
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+    };
+    kind.to_string()
+}
+
+// a copy of the event for the subscribers, the shared node event channel gets
+// the original
+fn copy(event: &dyn Event) -> Box<dyn Event> {
+    let any: &dyn Any = event;
+    if let Some(event) = any.downcast_ref::<NodeEvent>() {
+        Box::new(event.clone())
+    } else if let Some(event) = any.downcast_ref::<WebEvent>() {
+        Box::new(event.clone())
+    } else if let Some(event) = any.downcast_ref::<ShortcutEvent>() {
+        Box::new(event.clone())
+    } else if let Some(event) = any.downcast_ref::<FaultInjected>() {
+        Box::new(event.clone())
+    } else if let Some(event) = any.downcast_ref::<NodeCrashed>() {
+        Box::new(event.clone())
+    } else if let Some(event) = any.downcast_ref::<NodeRestarted>() {
+        Box::new(event.clone())
+    } else if let Some(event) = any.downcast_ref::<NodeRestartFailed>() {
+        Box::new(event.clone())
+    } else {
+        Box::new(OpaqueEvent(format!("{event:?}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ExecutionConfig;
+    use crate::link::Links;
+    use crate::utils::Channel;
+    use std::collections::HashMap;
+    use wg_internal::config::Config;
+    use wg_internal::network::SourceRoutingHeader;
+    use wg_internal::packet::{Ack, Packet, PacketType};
+
+    // a node event of a type the broadcaster cannot copy
+    #[derive(Debug, PartialEq)]
+    struct Beacon(u8);
+
+    impl Event for Beacon {
+        fn into_any(self: Box<Self>) -> Box<dyn Any> {
+            self
+        }
+    }
+
+    fn ack(hops: Vec<NodeId>, session_id: u64) -> Packet {
+        Packet {
+            routing_header: SourceRoutingHeader::new(hops, 1),
+            session_id,
+            pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+        }
+    }
+
+    #[test]
+    fn test_broadcaster_fan_out() {
+        let drone_2 = Channel::new();
+        let drone_3 = Channel::new();
+        let client_1: Channel<Box<dyn Event>> = Channel::new();
+        let drone_events = Channel::new();
+        let node_events: Channel<Box<dyn Event>> = Channel::new();
+        let destination = Channel::new();
+        let shutdown: Channel<()> = Channel::new();
+        let links = Links::new(shutdown.get_receiver(), None, &ExecutionConfig::default());
+        let network = NetworkHandle::new(
+            HashMap::new(),
+            HashMap::new(),
+            &HashMap::from([(1, destination.clone())]),
+            links.clone(),
+            Config {
+                drone: Vec::new(),
+                client: Vec::new(),
+                server: Vec::new(),
+            },
+        );
+        let broadcaster = Broadcaster::new();
+        let everything = broadcaster.subscribe(EventFilter::all());
+        let logger = broadcaster.subscribe(EventFilter::all());
+        let from_drone_3 = broadcaster.subscribe(EventFilter::all().with_nodes([3]));
+        let dropped = broadcaster.subscribe(EventFilter::all().with_kinds(["PacketDropped"]));
+        let handle = spawn_broadcaster(
+            EventSources {
+                drones: vec![(2, drone_2.get_receiver()), (3, drone_3.get_receiver())],
+                nodes: vec![(1, client_1.get_receiver())],
+            },
+            broadcaster.clone(),
+            drone_events.get_sender(),
+            node_events.get_sender(),
+            Some(network),
+        );
+
+        drone_2
+            .send(DroneEvent::PacketSent(ack(vec![1, 2], 1)))
+            .unwrap();
+        drone_3
+            .send(DroneEvent::PacketDropped(ack(vec![1, 3], 2)))
+            .unwrap();
+        drone_3
+            .send(DroneEvent::ControllerShortcut(ack(vec![4, 3, 1], 3)))
+            .unwrap();
+        client_1
+            .send(Box::new(NodeEvent::FloodStarted(7, 1)))
+            .unwrap();
+        client_1.send(Box::new(Beacon(9))).unwrap();
+        drop((drone_2, drone_3, client_1));
+        handle.join().unwrap();
+
+        // every subscriber sees the end of the stream once the sources are gone
+        let everything: Vec<SimulationEvent> = everything.iter().collect();
+        assert_eq!(everything.len(), 5);
+        assert_eq!(logger.iter().count(), 5);
+        assert!(from_drone_3.iter().all(|e| e.source() == 3));
+        let dropped: Vec<SimulationEvent> = dropped.iter().collect();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].kind(), "PacketDropped");
+        assert!(everything.iter().any(|e| matches!(
+            e.node_event::<ShortcutEvent>(),
+            Some(ShortcutEvent::Delivered {
+                to: 1,
+                session_id: 3
+            })
+        )));
+        assert!(
+            everything
+                .iter()
+                .any(|e| e.source() == 1 && e.node_event::<NodeEvent>().is_some())
+        );
+        assert!(
+            everything
+                .iter()
+                .filter(|e| e.kind() != "Beacon")
+                .all(|e| EVENT_KINDS.contains(&e.kind().as_str()))
+        );
+        // subscribers get the `Debug` form of an event type the crate does not know
+        let beacon = everything.iter().find(|e| e.kind() == "Beacon").unwrap();
+        assert_eq!(
+            beacon.node_event::<OpaqueEvent>(),
+            Some(&OpaqueEvent("Beacon(9)".to_string()))
+        );
+        // over the link from drone 3
+        assert_eq!(destination.get_receiver().recv().unwrap().session_id, 3);
+        drop(shutdown);
+        links.join();
+
+        // the shared channels still get every event as it was sent
+        assert_eq!(drone_events.get_receiver().try_iter().count(), 2);
+        let node_events: Vec<Box<dyn Event>> = node_events.get_receiver().try_iter().collect();
+        assert_eq!(node_events.len(), 3);
+        assert!(
+            node_events
+                .into_iter()
+                .any(|event| event.into_any().downcast_ref::<Beacon>() == Some(&Beacon(9)))
+        );
+        assert!(broadcaster.subscribe(EventFilter::all()).recv().is_err());
+        assert!(
+            [1, 2, 3]
+                .map(|id| broadcaster.last_event(id))
+                .iter()
+                .all(Option::is_some)
+        );
+        assert_eq!(broadcaster.last_event(4), None);
+    }
+}
@@ -0,0 +1,535 @@
+//! The `network-initializer` command line: validate, draw, run and generate
+//! configs without writing a test.
+
+use crate::broadcast::{EventFilter, SimulationEvent};
+use crate::conformance::run_conformance;
+use crate::errors::ConfigError;
+use crate::export::{GraphFormat, render};
+use crate::fault::FaultInjected;
+use crate::format::{ConfigFormat, config_to_string, read_config};
+use crate::network_initializer::{NetworkInitializer, Uninitialized};
+use crate::parser::Parse;
+pub use crate::parser::diagnostics;
+use crate::repl::Repl;
+use crate::scenario::Scenario;
+use crate::settings::SimulationSettings;
+use crate::shortcut::ShortcutEvent;
+use crate::supervisor::{NodeCrashed, NodeRestartFailed, NodeRestarted};
+use crate::topology::{self, Endpoints};
+use crate::utils::parse_duration;
+use common::types::{NodeEvent, WebEvent};
+use crossbeam::channel::{after, select};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+use wg_internal::controller::DroneEvent;
+use wg_internal::network::NodeId;
+use wg_internal::packet::{NackType, NodeType as PacketNodeType, Packet, PacketType};
+
+const USAGE: &str = "\
+Usage: network-initializer <command> [options]
+
+Commands:
+  validate <file>                  report every problem of a config
+  graph <file> [--format dot|mermaid]
+                                   print the topology as a diagram
+  run <file> [--duration 10s] [--events out.jsonl]
+                                   run the simulation and record its events
+  repl <file>                      run the simulation and control it from
+                                   the console, `help` lists the commands
+  scenario <file>                  run the [scenario] of a file and report
+                                   its expectations
+  conformance [--timeout 1s]       check every drone implementation and
+                                   print a pass/fail/hang/panic matrix
+  generate <topology> [--size N] [--clients N] [--servers N] [--pdr P]
+           [--seed S] [--degree D] [--format toml|json|yaml] [--output file]
+                                   print a config for double-chain, star,
+                                   butterfly, tree, subnet-triangles or random";
+
+// positional arguments and `--key value` options
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if let Some(key) = arg.strip_prefix("--") {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for --{key}"))?;
+                options.insert(key.to_string(), value.clone());
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+        Ok(Self {
+            positional,
+            options,
+        })
+    }
+
+    fn file(&self) -> Result<&str, String> {
+        self.positional
+            .get(1)
+            .map(String::as_str)
+            .ok_or_else(|| format!("Missing config file\n\n{USAGE}"))
+    }
+
+    fn option<T: std::str::FromStr>(&self, key: &str, default: T) -> Result<T, String> {
+        self.options.get(key).map_or(Ok(default), |value| {
+            value
+                .parse()
+                .map_err(|_| format!("Invalid value for --{key}: {value}"))
+        })
+    }
+}
+
+/// Runs the command line `args` (without the program name), writing its
+/// output to `out`
+///
+/// # Errors
+/// Returns the message to show when the command fails
+pub fn run(args: &[String], out: &mut impl Write) -> Result<(), String> {
+    let args = Args::parse(args)?;
+    match args.positional.first().map(String::as_str) {
+        Some("validate") => validate(&args, out),
+        Some("graph") => graph(&args, out),
+        Some("run") => run_simulation(&args, out),
+        Some("repl") => repl(&args, out),
+        Some("scenario") => scenario(&args, out),
+        Some("conformance") => conformance(&args, out),
+        Some("generate") => generate(&args, out),
+        Some("help") | None => writeln!(out, "{USAGE}").map_err(|e| e.to_string()),
+        Some(other) => Err(format!("Unknown command: {other}\n\n{USAGE}")),
+    }
+}
+
+fn validate(args: &Args, out: &mut impl Write) -> Result<(), String> {
+    let path = args.file()?;
+    let errors = diagnostics(path);
+    if errors.is_empty() {
+        let config = read_config(path, None).map_err(|e| e.to_string())?;
+        return writeln!(
+            out,
+            "{path}: ok ({} drones, {} clients, {} servers)",
+            config.drone.len(),
+            config.client.len(),
+            config.server.len()
+        )
+        .map_err(|e| e.to_string());
+    }
+    for error in &errors {
+        writeln!(out, "{path}: {error}").map_err(|e| e.to_string())?;
+    }
+    Err(format!("{path}: {} problem(s) found", errors.len()))
+}
+
+fn graph(args: &Args, out: &mut impl Write) -> Result<(), String> {
+    let path = args.file()?;
+    let config = read_config(path, None).map_err(|e| e.to_string())?;
+    let settings = SimulationSettings::parse_config(path).map_err(|e| e.to_string())?;
+    let format: GraphFormat = args.option("format", GraphFormat::Dot)?;
+    let implementations = settings.node_implementations(&config);
+    write!(out, "{}", render(&config, &implementations, format)).map_err(|e| e.to_string())
+}
+
+fn run_simulation(args: &Args, out: &mut impl Write) -> Result<(), String> {
+    let path = args.file()?;
+    let errors = diagnostics(path);
+    if let Some(error) = errors.first() {
+        return Err(format!("{path}: {error}"));
+    }
+    let duration = match args.options.get("duration") {
+        Some(duration) => parse_duration(duration)?,
+        None => Duration::from_secs(10),
+    };
+    let mut events: Box<dyn Write> = match args.options.get("events") {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).map_err(|e| format!("Cannot create {path}: {e}"))?,
+        )),
+        None => Box::new(std::io::sink()),
+    };
+
+    let initialized = NetworkInitializer::<Uninitialized>::new(path).initialize();
+    let received = initialized.subscribe(EventFilter::all());
+    let mut running = initialized.start_simulation();
+    let start = Instant::now();
+    let deadline = after(duration);
+    let mut counts = (0, 0);
+    // one JSON object per line: time since the start, source and event
+    let result = loop {
+        let event = select! {
+            recv(received) -> event => match event {
+                Ok(event) => event,
+                Err(_) => break Ok(()),
+            },
+            recv(deadline) -> _ => break Ok(()),
+        };
+        let source = match event {
+            SimulationEvent::Drone(..) => {
+                counts.0 += 1;
+                "drone"
+            }
+            SimulationEvent::Node(..) => {
+                counts.1 += 1;
+                "node"
+            }
+        };
+        let line = json!({
+            "at_ms": start.elapsed().as_millis(),
+            "source": source,
+            "id": event.source(),
+            "kind": event.kind(),
+            "event": event_json(&event),
+        });
+        if let Err(e) = writeln!(events, "{line}") {
+            break Err(e.to_string());
+        }
+    };
+    running.stop_simulation();
+    result?;
+    events.flush().map_err(|e| e.to_string())?;
+    writeln!(
+        out,
+        "{path}: {} drone events and {} node events in {duration:?}",
+        counts.0, counts.1
+    )
+    .map_err(|e| e.to_string())
+}
+
+fn repl(args: &Args, out: &mut impl Write) -> Result<(), String> {
+    let path = args.file()?;
+    if let Some(error) = diagnostics(path).first() {
+        return Err(format!("{path}: {error}"));
+    }
+    let running = NetworkInitializer::<Uninitialized>::new(path)
+        .initialize()
+        .start_simulation();
+    Repl::new(running)
+        .run(std::io::stdin().lock(), out)
+        .map_err(|e| e.to_string())
+}
+
+fn scenario(args: &Args, out: &mut impl Write) -> Result<(), String> {
+    let path = args.file()?;
+    let report = Scenario::from_file(path)
+        .and_then(Scenario::run)
+        .map_err(|e| format!("{path}: {e}"))?;
+    write!(out, "{report}").map_err(|e| e.to_string())?;
+    if report.passed() {
+        Ok(())
+    } else {
+        Err(format!("{path}: scenario failed"))
+    }
+}
+
+fn conformance(args: &Args, out: &mut impl Write) -> Result<(), String> {
+    let timeout = match args.options.get("timeout") {
+        Some(timeout) => parse_duration(timeout)?,
+        None => Duration::from_secs(1),
+    };
+    write!(out, "{}", run_conformance(timeout)).map_err(|e| e.to_string())
+}
+
+// the fields of an event, for the `--events` file of `run`
+fn event_json(event: &SimulationEvent) -> Value {
+    if let Some(event) = event.drone_event() {
+        let (DroneEvent::PacketSent(packet)
+        | DroneEvent::PacketDropped(packet)
+        | DroneEvent::ControllerShortcut(packet)) = event;
+        return json!({ "packet": packet_json(packet) });
+    }
+    if let Some(event) = event.node_event::<NodeEvent>() {
+        return match event {
+            NodeEvent::PacketSent(packet) => json!({ "packet": packet_json(packet) }),
+            NodeEvent::FloodStarted(flood_id, initiator) => {
+                json!({ "flood_id": flood_id, "initiator": initiator })
+            }
+            NodeEvent::MessageSent {
+                notification_from,
+                to,
+            } => json!({ "notification_from": notification_from, "to": to }),
+            NodeEvent::MessageReceived {
+                notification_from,
+                from,
+            } => json!({ "notification_from": notification_from, "from": from }),
+            NodeEvent::ServerTypeQueried { notification_from } => {
+                json!({ "notification_from": notification_from })
+            }
+        };
+    }
+    if let Some(event) = event.node_event::<WebEvent>() {
+        return match event {
+            WebEvent::FileOperationError {
+                notification_from,
+                msg,
+            } => json!({ "notification_from": notification_from, "msg": msg }),
+            WebEvent::TextFileAdded {
+                notification_from,
+                uuid,
+            } => json!({ "notification_from": notification_from, "uuid": uuid }),
+            WebEvent::FilesLists {
+                notification_from,
+                files_map,
+            } => json!({ "notification_from": notification_from, "files_map": files_map }),
+        };
+    }
+    if let Some(event) = event.node_event::<ShortcutEvent>() {
+        return match event {
+            ShortcutEvent::Delivered { to, session_id } => {
+                json!({ "to": to, "session_id": session_id })
+            }
+            ShortcutEvent::Undeliverable { to, session_id } => {
+                json!({ "to": to, "session_id": session_id })
+            }
+        };
+    }
+    if let Some(event) = event.node_event::<NodeCrashed>() {
+        return json!({ "kind": format!("{:?}", event.kind), "message": event.message });
+    }
+    if let Some(event) = event.node_event::<NodeRestarted>() {
+        return json!({ "kind": format!("{:?}", event.kind), "restarts": event.restarts });
+    }
+    if let Some(event) = event.node_event::<NodeRestartFailed>() {
+        return json!({ "error": event.error.to_string() });
+    }
+    if let Some(event) = event.node_event::<FaultInjected>() {
+        return json!({
+            "action": event.action,
+            "at_ms": event.at.as_millis(),
+            "error": event.result.as_ref().err().map(ToString::to_string),
+        });
+    }
+    match event {
+        SimulationEvent::Node(_, event) => json!({ "debug": format!("{event:?}") }),
+        SimulationEvent::Drone(..) => Value::Null,
+    }
+}
+
+fn packet_json(packet: &Packet) -> Value {
+    let mut fields = json!({
+        "session_id": packet.session_id,
+        "hops": packet.routing_header.hops,
+        "hop_index": packet.routing_header.hop_index,
+    });
+    let (kind, details) = match &packet.pack_type {
+        PacketType::MsgFragment(fragment) => (
+            "MsgFragment",
+            json!({
+                "fragment_index": fragment.fragment_index,
+                "total_n_fragments": fragment.total_n_fragments,
+                "length": fragment.length,
+            }),
+        ),
+        PacketType::Ack(ack) => ("Ack", json!({ "fragment_index": ack.fragment_index })),
+        PacketType::Nack(nack) => {
+            let (nack_type, node) = match nack.nack_type {
+                NackType::ErrorInRouting(node) => ("ErrorInRouting", Some(node)),
+                NackType::DestinationIsDrone => ("DestinationIsDrone", None),
+                NackType::Dropped => ("Dropped", None),
+                NackType::UnexpectedRecipient(node) => ("UnexpectedRecipient", Some(node)),
+            };
+            (
+                "Nack",
+                json!({
+                    "fragment_index": nack.fragment_index,
+                    "nack_type": nack_type,
+                    "node": node,
+                }),
+            )
+        }
+        PacketType::FloodRequest(request) => (
+            "FloodRequest",
+            json!({
+                "flood_id": request.flood_id,
+                "initiator_id": request.initiator_id,
+                "path_trace": path_trace_json(&request.path_trace),
+            }),
+        ),
+        PacketType::FloodResponse(response) => (
+            "FloodResponse",
+            json!({
+                "flood_id": response.flood_id,
+                "path_trace": path_trace_json(&response.path_trace),
+            }),
+        ),
+    };
+    fields["type"] = json!(kind);
+    if let (Some(fields), Value::Object(details)) = (fields.as_object_mut(), details) {
+        fields.extend(details);
+    }
+    fields
+}
+
+fn path_trace_json(path_trace: &[(NodeId, PacketNodeType)]) -> Value {
+    path_trace
+        .iter()
+        .map(|(id, kind)| json!([id, format!("{kind:?}")]))
+        .collect()
+}
+
+// a single PDR, or a range for the random topology
+fn parse_pdr(value: &str) -> Result<RangeInclusive<f32>, String> {
+    let invalid = || format!("Invalid value for --pdr: {value}");
+    match value.split_once("..") {
+        Some((start, end)) => {
+            let start = start.parse().map_err(|_| invalid())?;
+            let end = end.trim_start_matches('=').parse().map_err(|_| invalid())?;
+            Ok(start..=end)
+        }
+        None => {
+            let pdr = value.parse().map_err(|_| invalid())?;
+            Ok(pdr..=pdr)
+        }
+    }
+}
+
+fn generate(args: &Args, out: &mut impl Write) -> Result<(), String> {
+    let topology = args
+        .positional
+        .get(1)
+        .ok_or_else(|| format!("Missing topology\n\n{USAGE}"))?;
+    let pdr = parse_pdr(args.options.get("pdr").map_or("0.05", String::as_str))?;
+    let endpoints = Endpoints {
+        clients: args.option("clients", 2)?,
+        servers: args.option("servers", 2)?,
+        pdr: *pdr.start(),
+    };
+    if topology != "random" && pdr.start() != pdr.end() {
+        return Err("A PDR range is only supported by the random topology".to_string());
+    }
+    let config = match topology.as_str() {
+        "double-chain" => topology::double_chain(args.option("size", 5)?, endpoints),
+        "star" | "decagram" => {
+            topology::star(args.option("size", 10)?, args.option("step", 3)?, endpoints)
+        }
+        "butterfly" => topology::butterfly(args.option("size", 3)?, endpoints),
+        "tree" => topology::tree(
+            args.option("size", 2)?,
+            args.option("branching", 2)?,
+            endpoints,
+        ),
+        "subnet-triangles" => topology::subnet_triangles(args.option("size", 3)?, endpoints),
+        "random" => topology::random_topology(
+            args.option("seed", 0)?,
+            args.option("size", 10)?,
+            endpoints.clients,
+            endpoints.servers,
+            args.option("degree", 3.0)?,
+            pdr,
+        ),
+        other => return Err(format!("Unknown topology: {other}\n\n{USAGE}")),
+    }
+    .map_err(|e| e.to_string())?;
+
+    let output = args.options.get("output");
+    let format = match args.options.get("format") {
+        Some(format) => format.parse().map_err(|e: ConfigError| e.to_string())?,
+        None => output
+            .map_or(Ok(ConfigFormat::Toml), |path| ConfigFormat::from_path(path))
+            .map_err(|e| e.to_string())?,
+    };
+    let content = config_to_string(&config, format).map_err(|e| e.to_string())?;
+    match output {
+        Some(path) => {
+            std::fs::write(path, content).map_err(|e| format!("Cannot write {path}: {e}"))
+        }
+        None => write!(out, "{content}").map_err(|e| e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Validate;
+    use wg_internal::config::Config;
+
+    fn cli(args: &str) -> (Result<(), String>, String) {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        let mut out = Vec::new();
+        let result = run(&args, &mut out);
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_validate_command() {
+        let (result, out) = cli("validate ./tests/correct_config.toml");
+        assert!(result.is_ok());
+        assert_eq!(
+            out,
+            "./tests/correct_config.toml: ok (3 drones, 2 clients, 1 servers)\n"
+        );
+
+        let (result, out) = cli("validate ./tests/invalid_fault.toml");
+        assert!(result.is_err());
+        assert!(out.contains("Invalid fault"));
+
+        let (result, out) = cli("validate ./tests/invalid_scenario.toml");
+        assert!(result.is_err());
+        assert!(out.contains("Invalid scenario: Node 1 is not a client or a server"));
+
+        assert!(cli("validate").0.is_err());
+        assert!(cli("fly").0.is_err());
+    }
+
+    #[test]
+    fn test_run_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let events = dir.path().join("events.jsonl");
+        let (result, out) = cli(&format!(
+            "run ./tests/correct_config.toml --duration 300ms --events {}",
+            events.display()
+        ));
+        assert!(result.is_ok(), "{out}");
+
+        let lines: Vec<Value> = std::fs::read_to_string(&events)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert!(lines.iter().any(|line| {
+            line["source"] == "node"
+                && line["kind"] == "FloodStarted"
+                && line["event"]["flood_id"].is_u64()
+        }));
+        assert!(lines.iter().any(|line| {
+            line["source"] == "drone"
+                && line["kind"] == "PacketSent"
+                && line["event"]["packet"]["type"] == "FloodRequest"
+                && line["event"]["packet"]["hops"].is_array()
+        }));
+    }
+
+    #[test]
+    fn test_graph_and_generate_commands() {
+        let (result, out) = cli("graph ./tests/correct_config.toml --format mermaid");
+        assert!(result.is_ok());
+        assert!(out.starts_with("graph LR\n"));
+        assert!(out.contains("n4[\"Client 4<br/>WebBrowser\"]"), "{out}");
+        let (result, out) = cli("graph ./tests/template_config.toml");
+        assert!(result.is_ok());
+        assert!(out.contains("label=\"Drone 2\\nRustyDrone"), "{out}");
+
+        let (result, out) = cli("generate random --size 8 --seed 3 --pdr 0..0.2 --format json");
+        assert!(result.is_ok());
+        let config: Config = serde_json::from_str(&out).unwrap();
+        assert_eq!(config.drone.len(), 8);
+        config.validate_config().unwrap();
+
+        let (result, out) = cli("generate star --size 10 --step 3");
+        assert!(result.is_ok());
+        let config: Config = toml::from_str(&out).unwrap();
+        assert_eq!(config.drone.len(), 10);
+
+        assert!(cli("generate tree --pdr 0..0.2").0.is_err());
+        assert!(cli("generate moebius").0.is_err());
+    }
+}
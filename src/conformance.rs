@@ -0,0 +1,418 @@
+//! Conformance runner for the registered drone implementations, enabled or
+//! not: each one runs a set of standard checks on a small bench of its own drones, wired to probe
+//! endpoints driven by the runner, and gets a verdict per check.
+//!
+//! - `forward`: a fragment crosses a chain of two drones
+//! - `flood`: a flood request from an endpoint comes back as a flood response
+//! - `drop`: a drone with PDR 1 answers a fragment with a `Dropped` nack and
+//!   reports `PacketDropped`
+//! - `crash`: a drone stops after `Crash` once its neighbors removed it
+
+use crate::utils::{
+    drone_implementation_enabled, drone_implementation_names, generate_drone, panic_message,
+};
+use crossbeam::channel::{Receiver, Sender, unbounded};
+use std::collections::HashMap;
+use std::fmt;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use wg_internal::controller::{DroneCommand, DroneEvent};
+use wg_internal::network::{NodeId, SourceRoutingHeader};
+use wg_internal::packet::{FloodRequest, Fragment, Nack, NackType, NodeType, Packet, PacketType};
+
+// the runner's endpoints, never drones
+const PROBE: NodeId = 100;
+const OTHER_PROBE: NodeId = 101;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Pass,
+    Fail(String),
+    // the drone did not stop in time
+    Hang,
+    Panic(String),
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Pass => write!(f, "pass"),
+            Verdict::Fail(_) => write!(f, "FAIL"),
+            Verdict::Hang => write!(f, "HANG"),
+            Verdict::Panic(_) => write!(f, "PANIC"),
+        }
+    }
+}
+
+type Check = fn(usize, Duration) -> Result<(), Verdict>;
+
+const CHECKS: &[(&str, Check)] = &[
+    ("forward", check_forward),
+    ("flood", check_flood),
+    ("drop", check_drop),
+    ("crash", check_crash),
+];
+
+/// Verdicts of one drone implementation
+#[derive(Debug)]
+pub struct ConformanceRow {
+    pub implementation: &'static str,
+    // picked for the drones without an `implementation`
+    pub enabled: bool,
+    // in `ConformanceMatrix::checks` order
+    pub verdicts: Vec<Verdict>,
+}
+
+/// Verdict of every check for every registered drone implementation
+#[derive(Debug)]
+pub struct ConformanceMatrix {
+    pub checks: Vec<&'static str>,
+    pub rows: Vec<ConformanceRow>,
+}
+
+impl ConformanceMatrix {
+    #[must_use]
+    pub fn verdict(&self, implementation: &str, check: &str) -> Option<&Verdict> {
+        let column = self.checks.iter().position(|c| *c == check)?;
+        self.rows
+            .iter()
+            .find(|row| row.implementation == implementation)
+            .map(|row| &row.verdicts[column])
+    }
+}
+
+impl fmt::Display for ConformanceMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .rows
+            .iter()
+            .map(|row| row.implementation.len())
+            .chain(["implementation".len()])
+            .max()
+            .unwrap_or_default();
+        write!(f, "{:width$}  {:8}", "implementation", "enabled")?;
+        for check in &self.checks {
+            write!(f, "  {check:8}")?;
+        }
+        writeln!(f)?;
+        for row in &self.rows {
+            let enabled = if row.enabled { "yes" } else { "no" };
+            write!(f, "{:width$}  {enabled:8}", row.implementation)?;
+            for verdict in &row.verdicts {
+                write!(f, "  {:8}", verdict.to_string())?;
+            }
+            writeln!(f)?;
+        }
+        for row in &self.rows {
+            for (check, verdict) in self.checks.iter().zip(&row.verdicts) {
+                if let Verdict::Fail(reason) | Verdict::Panic(reason) = verdict {
+                    writeln!(f, "{} {check}: {reason}", row.implementation)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs every check on every registered drone implementation, including the
+/// ones not picked by default; `timeout` bounds each wait of a check
+#[must_use]
+pub fn run_conformance(timeout: Duration) -> ConformanceMatrix {
+    let rows = drone_implementation_names()
+        .iter()
+        .enumerate()
+        .map(|(i, name)| ConformanceRow {
+            implementation: name,
+            enabled: drone_implementation_enabled(i),
+            verdicts: CHECKS
+                .iter()
+                .map(|(_, check)| run_check(*check, i, timeout))
+                .collect(),
+        })
+        .collect();
+    ConformanceMatrix {
+        checks: CHECKS.iter().map(|(name, _)| *name).collect(),
+        rows,
+    }
+}
+
+// a check runs on its own thread, so a panic while building the drones or a
+// check stuck on a drone does not take the runner down
+fn run_check(check: Check, implementation: usize, timeout: Duration) -> Verdict {
+    let (sender, verdict) = unbounded();
+    let handle = std::thread::spawn(move || {
+        let _ = sender.send(check(implementation, timeout));
+    });
+    match verdict.recv_timeout(timeout * 4) {
+        Ok(Ok(())) => Verdict::Pass,
+        Ok(Err(verdict)) => verdict,
+        Err(_) if handle.is_finished() => match handle.join() {
+            Err(panic) => Verdict::Panic(panic_message(panic.as_ref())),
+            Ok(()) => Verdict::Fail("The check stopped without a verdict".to_string()),
+        },
+        Err(_) => Verdict::Hang,
+    }
+}
+
+// drones of one implementation, wired to each other and to the probes
+struct Bench {
+    commands: HashMap<NodeId, Sender<DroneCommand>>,
+    packets: HashMap<NodeId, Sender<Packet>>,
+    probes: HashMap<NodeId, Receiver<Packet>>,
+    events: Receiver<DroneEvent>,
+    handles: HashMap<NodeId, JoinHandle<()>>,
+}
+
+impl Bench {
+    // `drones` are (id, neighbors, pdr), neighbors may be probes
+    fn new(implementation: usize, drones: &[(NodeId, &[NodeId], f32)]) -> Self {
+        let (event_sender, events) = unbounded();
+        let mut packets = HashMap::new();
+        let mut receivers = HashMap::new();
+        for (id, _, _) in drones {
+            let (sender, receiver) = unbounded();
+            packets.insert(*id, sender);
+            receivers.insert(*id, receiver);
+        }
+        let mut probes = HashMap::new();
+        let mut probe_senders = HashMap::new();
+        for probe in [PROBE, OTHER_PROBE] {
+            let (sender, receiver) = unbounded();
+            probe_senders.insert(probe, sender);
+            probes.insert(probe, receiver);
+        }
+
+        let mut commands = HashMap::new();
+        let mut handles = HashMap::new();
+        for (id, neighbors, pdr) in drones {
+            let (command_sender, command_receiver) = unbounded();
+            let neighbors = neighbors
+                .iter()
+                .filter_map(|n| {
+                    packets
+                        .get(n)
+                        .or_else(|| probe_senders.get(n))
+                        .map(|sender| (*n, sender.clone()))
+                })
+                .collect();
+            let mut drone = generate_drone(
+                implementation,
+                &event_sender,
+                (
+                    *id,
+                    command_receiver,
+                    receivers.remove(id).expect("Created above"),
+                    neighbors,
+                    *pdr,
+                ),
+            );
+            commands.insert(*id, command_sender);
+            handles.insert(*id, std::thread::spawn(move || drone.run()));
+        }
+        Self {
+            commands,
+            packets,
+            probes,
+            events,
+            handles,
+        }
+    }
+
+    fn send(&self, to: NodeId, packet: Packet) {
+        let _ = self.packets[&to].send(packet);
+    }
+
+    // the first packet reaching `probe` for which `accept` holds
+    fn expect_at(
+        &self,
+        probe: NodeId,
+        timeout: Duration,
+        what: &str,
+        accept: impl Fn(&Packet) -> bool,
+    ) -> Result<Packet, Verdict> {
+        let deadline = Instant::now() + timeout;
+        while let Ok(packet) = self.probes[&probe].recv_deadline(deadline) {
+            if accept(&packet) {
+                return Ok(packet);
+            }
+        }
+        Err(Verdict::Fail(format!("No {what} within {timeout:?}")))
+    }
+
+    // crashes the drones without waiting for them: a drone that hangs is
+    // left behind. A drone that panicked turns any result into a panic
+    fn finish(mut self, result: Result<(), Verdict>) -> Result<(), Verdict> {
+        for sender in self.commands.values() {
+            let _ = sender.send(DroneCommand::Crash);
+        }
+        self.packets.clear();
+        for (_, handle) in self.handles.drain() {
+            if handle.is_finished()
+                && let Err(panic) = handle.join()
+            {
+                return Err(Verdict::Panic(panic_message(panic.as_ref())));
+            }
+        }
+        result
+    }
+}
+
+fn fragment(hops: Vec<NodeId>, session_id: u64) -> Packet {
+    Packet {
+        routing_header: SourceRoutingHeader::new(hops, 1),
+        session_id,
+        pack_type: PacketType::MsgFragment(Fragment {
+            fragment_index: 0,
+            total_n_fragments: 1,
+            length: 5,
+            data: [7; 128],
+        }),
+    }
+}
+
+fn check_forward(implementation: usize, timeout: Duration) -> Result<(), Verdict> {
+    let bench = Bench::new(
+        implementation,
+        &[(1, &[PROBE, 2], 0.0), (2, &[1, OTHER_PROBE], 0.0)],
+    );
+    bench.send(1, fragment(vec![PROBE, 1, 2, OTHER_PROBE], 1));
+    let result = bench
+        .expect_at(OTHER_PROBE, timeout, "fragment", |p| {
+            matches!(p.pack_type, PacketType::MsgFragment(_))
+        })
+        .and_then(|packet| match packet.routing_header.hop_index {
+            3 => Ok(()),
+            other => Err(Verdict::Fail(format!(
+                "Fragment arrived with hop index {other}"
+            ))),
+        });
+    bench.finish(result)
+}
+
+fn check_flood(implementation: usize, timeout: Duration) -> Result<(), Verdict> {
+    let bench = Bench::new(
+        implementation,
+        &[
+            (1, &[PROBE, 2, 3], 0.0),
+            (2, &[1, 3], 0.0),
+            (3, &[1, 2], 0.0),
+        ],
+    );
+    let mut request = FloodRequest::new(1, PROBE);
+    request.path_trace.push((PROBE, NodeType::Client));
+    bench.send(
+        1,
+        Packet::new_flood_request(SourceRoutingHeader::empty_route(), 1, request),
+    );
+    let result = bench
+        .expect_at(
+            PROBE,
+            timeout,
+            "flood response",
+            |p| matches!(&p.pack_type, PacketType::FloodResponse(r) if r.flood_id == 1),
+        )
+        .and_then(|packet| match packet.pack_type {
+            PacketType::FloodResponse(response)
+                if response.path_trace.first() == Some(&(PROBE, NodeType::Client))
+                    && response.path_trace.get(1) == Some(&(1, NodeType::Drone)) =>
+            {
+                Ok(())
+            }
+            other => Err(Verdict::Fail(format!(
+                "Unexpected flood response {other:?}"
+            ))),
+        });
+    bench.finish(result)
+}
+
+fn check_drop(implementation: usize, timeout: Duration) -> Result<(), Verdict> {
+    let bench = Bench::new(implementation, &[(1, &[PROBE, OTHER_PROBE], 1.0)]);
+    bench.send(1, fragment(vec![PROBE, 1, OTHER_PROBE], 1));
+    let result = bench
+        .expect_at(PROBE, timeout, "nack", |p| {
+            matches!(p.pack_type, PacketType::Nack(_))
+        })
+        .and_then(|packet| match packet.pack_type {
+            PacketType::Nack(Nack {
+                nack_type: NackType::Dropped,
+                ..
+            }) => Ok(()),
+            other => Err(Verdict::Fail(format!(
+                "Expected a Dropped nack, got {other:?}"
+            ))),
+        })
+        .and_then(|()| {
+            let deadline = Instant::now() + timeout;
+            while let Ok(event) = bench.events.recv_deadline(deadline) {
+                if matches!(event, DroneEvent::PacketDropped(_)) {
+                    return Ok(());
+                }
+            }
+            Err(Verdict::Fail("No PacketDropped event".to_string()))
+        });
+    bench.finish(result)
+}
+
+fn check_crash(implementation: usize, timeout: Duration) -> Result<(), Verdict> {
+    let mut bench = Bench::new(implementation, &[(1, &[PROBE, 2], 0.0), (2, &[1], 0.0)]);
+    let _ = bench.commands[&2].send(DroneCommand::RemoveSender(1));
+    let _ = bench.commands[&1].send(DroneCommand::Crash);
+    // drone 2 and the runner were the only ones able to reach drone 1
+    bench.packets.remove(&1);
+    let handle = bench.handles.remove(&1).expect("Drone 1 is on the bench");
+    let deadline = Instant::now() + timeout;
+    while !handle.is_finished() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let result = if handle.is_finished() {
+        handle
+            .join()
+            .map_err(|panic| Verdict::Panic(panic_message(panic.as_ref())))
+    } else {
+        Err(Verdict::Hang)
+    };
+    bench.finish(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conformance_matrix() {
+        let matrix = run_conformance(Duration::from_millis(100));
+        assert_eq!(matrix.checks, vec!["forward", "flood", "drop", "crash"]);
+        let names: Vec<_> = matrix.rows.iter().map(|row| row.implementation).collect();
+        assert_eq!(names, drone_implementation_names());
+        assert!(matrix.rows.iter().all(|row| row.verdicts.len() == 4));
+        assert!(matrix.verdict("RustBustersDrone", "fly").is_none());
+
+        // `drone_factories!` enables exactly the implementations that never panic
+        for row in &matrix.rows {
+            let panics = row.verdicts.iter().any(|v| matches!(v, Verdict::Panic(_)));
+            assert_eq!(row.enabled, !panics, "{} enabled flag", row.implementation);
+        }
+        for check in ["forward", "crash"] {
+            assert_eq!(
+                matrix.verdict("RustBustersDrone", check),
+                Some(&Verdict::Pass),
+                "RustBustersDrone {check}"
+            );
+        }
+        assert!(matches!(
+            matrix.verdict("LockheedRustin", "forward"),
+            Some(Verdict::Panic(_))
+        ));
+        let lockheed = matrix
+            .rows
+            .iter()
+            .find(|row| row.implementation == "LockheedRustin")
+            .unwrap();
+        assert!(!lockheed.enabled);
+
+        let report = matrix.to_string();
+        assert!(report.starts_with("implementation"));
+        assert!(report.contains("LockheedRustin"));
+        assert!(report.contains("LockheedRustin forward: "));
+    }
+}
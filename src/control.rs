@@ -0,0 +1,487 @@
+use crate::errors::ControlError;
+use crate::link::{LinkConfig, Links};
+use crate::shortcut::{ShortcutEvent, deliver};
+use crate::utils::{Channel, NodeType as ConfigNode};
+use common::network::Network;
+use common::types::{Command, NodeCommand, NodeType as CommonNodeType};
+use crossbeam::channel::Sender;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use wg_internal::config::{Client, Config, Drone, Server};
+use wg_internal::controller::DroneCommand;
+use wg_internal::network::NodeId;
+use wg_internal::packet::{NodeType, Packet};
+
+pub(crate) type NodeCommandSenders = HashMap<NodeId, (CommonNodeType, Sender<Box<dyn Command>>)>;
+
+/// Cloneable access to a running network: the command channels of every node
+/// and the live topology, kept up to date by every change made through it.
+#[derive(Clone)]
+pub struct NetworkHandle {
+    // command channel of each node, shared so the channels of a restarted
+    // node replace the old ones for every handle
+    drone_commands: Arc<RwLock<HashMap<NodeId, Sender<DroneCommand>>>>,
+    node_commands: Arc<RwLock<NodeCommandSenders>>,
+    // packet channel of each node, shared so a node released by one handle is
+    // released by all of them
+    packet_senders: Arc<RwLock<HashMap<NodeId, Sender<Packet>>>>,
+    // links between the nodes, new ones are created by `add_link`
+    links: Links,
+    topology: Arc<RwLock<Config>>,
+}
+
+impl NetworkHandle {
+    pub(crate) fn new(
+        drone_commands: HashMap<NodeId, Sender<DroneCommand>>,
+        node_commands: HashMap<NodeId, (CommonNodeType, Sender<Box<dyn Command>>)>,
+        communications_channels: &HashMap<NodeId, Channel<Packet>>,
+        links: Links,
+        topology: Config,
+    ) -> Self {
+        Self {
+            drone_commands: Arc::new(RwLock::new(drone_commands)),
+            node_commands: Arc::new(RwLock::new(node_commands)),
+            packet_senders: Arc::new(RwLock::new(
+                communications_channels
+                    .iter()
+                    .map(|(id, channel)| (*id, channel.get_sender()))
+                    .collect(),
+            )),
+            links,
+            topology: Arc::new(RwLock::new(topology)),
+        }
+    }
+
+    /// Crashes a drone and removes it from the senders of its neighbors. The
+    /// links into it are retired, so it stops receiving packets
+    ///
+    /// # Errors
+    /// Returns an error if `id` is not a drone of the network or a command
+    /// cannot be delivered
+    pub fn crash_drone(&self, id: NodeId) -> Result<(), ControlError> {
+        let neighbors = {
+            let mut topology = self.write_topology();
+            let Some(index) = topology.drone.iter().position(|d| d.id == id) else {
+                return Err(Self::missing_drone(&topology, id));
+            };
+            let drone = topology.drone.remove(index);
+            for neighbor in &drone.connected_node_ids {
+                if let Some(ids) = neighbors_mut(&mut topology, *neighbor) {
+                    ids.retain(|n| *n != id);
+                }
+            }
+            drone.connected_node_ids
+        };
+
+        // the drone is told last: once it stops, the supervisor may start it
+        // again and connect it to its neighbors
+        self.links.disconnect(id);
+        for neighbor in neighbors {
+            self.remove_sender(neighbor, id)?;
+        }
+        self.send_drone_command(id, DroneCommand::Crash)
+    }
+
+    /// Changes the packet drop rate of a drone
+    ///
+    /// # Errors
+    /// Returns an error if `id` is not a drone of the network, `pdr` is not in
+    /// `[0, 1]` or the command cannot be delivered
+    pub fn set_pdr(&self, id: NodeId, pdr: f32) -> Result<(), ControlError> {
+        if !(0.0..=1.0).contains(&pdr) {
+            return Err(ControlError::InvalidPdrValue(pdr));
+        }
+        {
+            let mut topology = self.write_topology();
+            let missing = Self::missing_drone(&topology, id);
+            let drone = topology
+                .drone
+                .iter_mut()
+                .find(|d| d.id == id)
+                .ok_or(missing)?;
+            drone.pdr = pdr;
+        }
+        self.send_drone_command(id, DroneCommand::SetPacketDropRate(pdr))
+    }
+
+    /// Connects two nodes in both directions
+    ///
+    /// # Errors
+    /// Returns an error if a node is not in the network, the nodes are already
+    /// connected or a command cannot be delivered
+    pub fn add_link(&self, a: NodeId, b: NodeId) -> Result<(), ControlError> {
+        {
+            let mut topology = self.write_topology();
+            for (from, to) in [(a, b), (b, a)] {
+                let ids =
+                    neighbors_mut(&mut topology, from).ok_or(ControlError::UnknownNode(from))?;
+                if ids.contains(&to) {
+                    return Err(ControlError::AlreadyConnected(a, b));
+                }
+            }
+            for (from, to) in [(a, b), (b, a)] {
+                if let Some(ids) = neighbors_mut(&mut topology, from) {
+                    ids.push(to);
+                }
+            }
+        }
+        self.add_sender(a, b)?;
+        self.add_sender(b, a)
+    }
+
+    /// Disconnects two nodes in both directions
+    ///
+    /// # Errors
+    /// Returns an error if a node is not in the network, the nodes are not
+    /// connected or a command cannot be delivered
+    pub fn remove_link(&self, a: NodeId, b: NodeId) -> Result<(), ControlError> {
+        {
+            let mut topology = self.write_topology();
+            for (from, to) in [(a, b), (b, a)] {
+                let ids =
+                    neighbors_mut(&mut topology, from).ok_or(ControlError::UnknownNode(from))?;
+                if !ids.contains(&to) {
+                    return Err(ControlError::NotConnected(a, b));
+                }
+            }
+            for (from, to) in [(a, b), (b, a)] {
+                if let Some(ids) = neighbors_mut(&mut topology, from) {
+                    ids.retain(|id| *id != to);
+                }
+            }
+        }
+        self.remove_sender(a, b)?;
+        self.remove_sender(b, a)
+    }
+
+    /// Delivers a packet handed over by drone `from` with
+    /// `DroneEvent::ControllerShortcut` to the last hop of its routing header,
+    /// over a link from the drone
+    pub(crate) fn deliver_shortcut(&self, from: NodeId, packet: Packet) -> ShortcutEvent {
+        deliver(packet, |to| {
+            let destination = self.packet_sender(to).ok()?;
+            Some(self.links.connect(from, to, None, destination))
+        })
+    }
+
+    /// Body of the thread delivering the shortcuts, see [`Links::watched_shortcuts`]
+    pub(crate) fn watched_shortcuts(
+        &self,
+        run: impl FnOnce() + Send + 'static,
+    ) -> Box<dyn FnOnce() + Send> {
+        self.links.watched_shortcuts(run)
+    }
+
+    /// Removes a node whose thread stopped from the topology and from the
+    /// senders of its neighbors
+    ///
+    /// # Errors
+    /// Returns an error if `id` is not in the network or a command cannot be
+    /// delivered
+    pub(crate) fn remove_node(&self, id: NodeId) -> Result<(), ControlError> {
+        let adjacents = {
+            let mut topology = self.write_topology();
+            let adjacents = neighbors(&topology, id)
+                .cloned()
+                .ok_or(ControlError::UnknownNode(id))?;
+            topology.drone.retain(|d| d.id != id);
+            topology.client.retain(|c| c.id != id);
+            topology.server.retain(|s| s.id != id);
+            for neighbor in &adjacents {
+                if let Some(ids) = neighbors_mut(&mut topology, *neighbor) {
+                    ids.retain(|n| *n != id);
+                }
+            }
+            adjacents
+        };
+        // every neighbor is told, even if one of them is gone too
+        adjacents
+            .into_iter()
+            .map(|neighbor| self.remove_sender(neighbor, id))
+            .fold(Ok(()), Result::and)
+    }
+
+    /// Puts a restarted node back in the topology, connected to the nodes of
+    /// `original` still in the network. A node that never left keeps its
+    /// neighbors. Either way they get a sender to its new packet channel, see
+    /// [`Self::replace_drone_channels`]. Returns new senders from the node to
+    /// each of them
+    ///
+    /// # Errors
+    /// Returns an error if a neighbor is not in the network or a command
+    /// cannot be delivered
+    pub(crate) fn restore_node(
+        &self,
+        original: &ConfigNode<'_>,
+    ) -> Result<HashMap<NodeId, Sender<Packet>>, ControlError> {
+        let id = original.id();
+        let adjacents = {
+            let mut topology = self.write_topology();
+            if let Some(ids) = neighbors(&topology, id) {
+                ids.clone()
+            } else {
+                let adjacents: Vec<NodeId> = original
+                    .connected_node_ids()
+                    .iter()
+                    .copied()
+                    .filter(|n| neighbors(&topology, *n).is_some())
+                    .collect();
+                match original {
+                    ConfigNode::Drone(d) => topology.drone.push(Drone {
+                        connected_node_ids: adjacents.clone(),
+                        ..(*d).clone()
+                    }),
+                    ConfigNode::Client(c) => topology.client.push(Client {
+                        connected_drone_ids: adjacents.clone(),
+                        ..(*c).clone()
+                    }),
+                    ConfigNode::Server(s) => topology.server.push(Server {
+                        connected_drone_ids: adjacents.clone(),
+                        ..(*s).clone()
+                    }),
+                }
+                for neighbor in &adjacents {
+                    if let Some(ids) = neighbors_mut(&mut topology, *neighbor) {
+                        ids.push(id);
+                    }
+                }
+                adjacents
+            }
+        };
+
+        let mut senders = HashMap::new();
+        for neighbor in adjacents {
+            self.add_sender(neighbor, id)?;
+            let destination = self.packet_sender(neighbor)?;
+            senders.insert(
+                neighbor,
+                self.links.connect(id, neighbor, None, destination),
+            );
+        }
+        Ok(senders)
+    }
+
+    /// Impairments of the link from `from` to `to`, `None` if none was started
+    /// or it was retired
+    #[must_use]
+    pub fn link_config(&self, from: NodeId, to: NodeId) -> Option<LinkConfig> {
+        self.links.config(from, to)
+    }
+
+    /// Every link of the network, once per pair of nodes
+    #[must_use]
+    pub fn links(&self) -> Vec<(NodeId, NodeId)> {
+        config_links(&self.read_topology())
+    }
+
+    /// The network as it is now, after every change made through this handle
+    #[must_use]
+    pub fn network_view(&self) -> Network {
+        build_network_view(&self.read_topology())
+    }
+
+    /// Drops every sender to the packet channel of a node that is stopping,
+    /// the ones of this handle and of the links into it, so the node sees its
+    /// packet channel disconnect, and forgets its command channel
+    pub(crate) fn release_node(&self, id: NodeId) {
+        self.packet_senders
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .remove(&id);
+        self.links.disconnect(id);
+        write_lock(&self.drone_commands).remove(&id);
+        write_lock(&self.node_commands).remove(&id);
+    }
+
+    /// Gives a restarted drone new command and packet channels. The links
+    /// into it are retired, [`Self::restore_node`] connects its neighbors to
+    /// the new packet channel
+    pub(crate) fn replace_drone_channels(
+        &self,
+        id: NodeId,
+        commands: Sender<DroneCommand>,
+        packets: Sender<Packet>,
+    ) {
+        write_lock(&self.drone_commands).insert(id, commands);
+        self.replace_packet_channel(id, packets);
+    }
+
+    /// Like [`Self::replace_drone_channels`] for a client or a server
+    pub(crate) fn replace_node_channels(
+        &self,
+        id: NodeId,
+        commands: Sender<Box<dyn Command>>,
+        packets: Sender<Packet>,
+    ) {
+        if let Some((_, sender)) = write_lock(&self.node_commands).get_mut(&id) {
+            *sender = commands;
+        }
+        self.replace_packet_channel(id, packets);
+    }
+
+    fn replace_packet_channel(&self, id: NodeId, packets: Sender<Packet>) {
+        self.packet_senders
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(id, packets);
+        self.links.disconnect(id);
+    }
+
+    /// Current command channel of every drone
+    pub(crate) fn drone_command_senders(&self) -> HashMap<NodeId, Sender<DroneCommand>> {
+        read_lock(&self.drone_commands).clone()
+    }
+
+    /// Current command channel of every client and server, with its type
+    pub(crate) fn node_command_senders(&self) -> NodeCommandSenders {
+        read_lock(&self.node_commands).clone()
+    }
+
+    fn packet_sender(&self, id: NodeId) -> Result<Sender<Packet>, ControlError> {
+        self.packet_senders
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(&id)
+            .cloned()
+            .ok_or(ControlError::UnknownNode(id))
+    }
+
+    fn add_sender(&self, node: NodeId, neighbor: NodeId) -> Result<(), ControlError> {
+        let destination = self.packet_sender(neighbor)?;
+        let sender = self.links.connect(node, neighbor, None, destination);
+        if read_lock(&self.drone_commands).contains_key(&node) {
+            self.send_drone_command(node, DroneCommand::AddSender(neighbor, sender))
+        } else {
+            self.send_node_command(node, NodeCommand::AddSender(neighbor, sender))
+        }
+    }
+
+    fn remove_sender(&self, node: NodeId, neighbor: NodeId) -> Result<(), ControlError> {
+        if read_lock(&self.drone_commands).contains_key(&node) {
+            self.send_drone_command(node, DroneCommand::RemoveSender(neighbor))
+        } else {
+            self.send_node_command(node, NodeCommand::RemoveSender(neighbor))
+        }
+    }
+
+    fn send_drone_command(&self, id: NodeId, command: DroneCommand) -> Result<(), ControlError> {
+        read_lock(&self.drone_commands)
+            .get(&id)
+            .ok_or(ControlError::NotADrone(id))?
+            .send(command)
+            .map_err(|_| ControlError::ChannelClosed(id))
+    }
+
+    fn send_node_command(&self, id: NodeId, command: NodeCommand) -> Result<(), ControlError> {
+        read_lock(&self.node_commands)
+            .get(&id)
+            .ok_or(ControlError::UnknownNode(id))?
+            .1
+            .send(Box::new(command))
+            .map_err(|_| ControlError::ChannelClosed(id))
+    }
+
+    fn missing_drone(topology: &Config, id: NodeId) -> ControlError {
+        if neighbors(topology, id).is_some() {
+            ControlError::NotADrone(id)
+        } else {
+            ControlError::UnknownNode(id)
+        }
+    }
+
+    pub(crate) fn read_topology(&self) -> RwLockReadGuard<'_, Config> {
+        self.topology
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn write_topology(&self) -> RwLockWriteGuard<'_, Config> {
+        self.topology
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+fn read_lock<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+fn write_lock<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+pub(crate) fn build_network_view(config: &Config) -> Network {
+    let mut network = Network::default();
+    for d in &config.drone {
+        network.add_node_controller_view(d.id, NodeType::Drone, &d.connected_node_ids);
+    }
+    for c in &config.client {
+        network.add_node_controller_view(c.id, NodeType::Client, &c.connected_drone_ids);
+    }
+    for s in &config.server {
+        network.add_node_controller_view(s.id, NodeType::Server, &s.connected_drone_ids);
+    }
+    network
+}
+
+/// Every link of a config, once per pair of nodes
+pub(crate) fn config_links(config: &Config) -> Vec<(NodeId, NodeId)> {
+    let mut links: Vec<(NodeId, NodeId)> = config
+        .drone
+        .iter()
+        .flat_map(|d| d.connected_node_ids.iter().map(move |id| (d.id, *id)))
+        .chain(
+            config
+                .client
+                .iter()
+                .flat_map(|c| c.connected_drone_ids.iter().map(move |id| (c.id, *id))),
+        )
+        .chain(
+            config
+                .server
+                .iter()
+                .flat_map(|s| s.connected_drone_ids.iter().map(move |id| (s.id, *id))),
+        )
+        .filter(|(a, b)| a < b)
+        .collect();
+    links.sort_unstable();
+    links
+}
+
+fn neighbors(config: &Config, id: NodeId) -> Option<&Vec<NodeId>> {
+    config
+        .drone
+        .iter()
+        .find(|d| d.id == id)
+        .map(|d| &d.connected_node_ids)
+        .or_else(|| {
+            config
+                .client
+                .iter()
+                .find(|c| c.id == id)
+                .map(|c| &c.connected_drone_ids)
+        })
+        .or_else(|| {
+            config
+                .server
+                .iter()
+                .find(|s| s.id == id)
+                .map(|s| &s.connected_drone_ids)
+        })
+}
+
+fn neighbors_mut(config: &mut Config, id: NodeId) -> Option<&mut Vec<NodeId>> {
+    if let Some(d) = config.drone.iter_mut().find(|d| d.id == id) {
+        return Some(&mut d.connected_node_ids);
+    }
+    if let Some(c) = config.client.iter_mut().find(|c| c.id == id) {
+        return Some(&mut c.connected_drone_ids);
+    }
+    config
+        .server
+        .iter_mut()
+        .find(|s| s.id == id)
+        .map(|s| &mut s.connected_drone_ids)
+}
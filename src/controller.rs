@@ -0,0 +1,298 @@
+//! Simulation controller: owns a running network on its own thread, where it
+//! reads every drone and node event, publishes them to its subscribers and
+//! applies the commands it receives. Controller shortcuts are delivered by the
+//! network itself when it was built `with_shortcut_routing`.
+
+use crate::broadcast::{Broadcaster, EventFilter, SimulationEvent};
+use crate::errors::ControlError;
+use crate::network_initializer::{NetworkInitializer, Running};
+use common::network::Network;
+use common::types::Command;
+use crossbeam::channel::{Receiver, Sender, never, select, unbounded};
+use std::thread::JoinHandle;
+use wg_internal::network::NodeId;
+
+/// Commands applied by the controller thread
+#[derive(Debug)]
+pub enum ControllerCommand {
+    CrashDrone(NodeId),
+    SetPdr(NodeId, f32),
+    AddLink(NodeId, NodeId),
+    RemoveLink(NodeId, NodeId),
+    // a `NodeCommand`, `WebCommand`, ... for a client or a server
+    SendToNode(NodeId, Box<dyn Command>),
+    Partition(Vec<Vec<NodeId>>),
+    Heal,
+    Pause,
+    Resume,
+}
+
+enum Request {
+    Command(ControllerCommand, Sender<Result<(), ControlError>>),
+    NetworkView(Sender<Network>),
+}
+
+pub struct SimulationController {
+    subscribers: Broadcaster,
+    requests: Option<Sender<Request>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl SimulationController {
+    /// Moves the running simulation to the controller thread
+    #[must_use]
+    pub fn start(running: NetworkInitializer<Running>) -> Self {
+        let (requests, inbox) = unbounded();
+        let subscribers = Broadcaster::new();
+        let controller = ControllerLoop::new(running, subscribers.clone());
+        let handle = std::thread::spawn(move || controller.run(&inbox));
+        Self {
+            subscribers,
+            requests: Some(requests),
+            handle: Some(handle),
+        }
+    }
+
+    /// Every event matching `filter` published from now on
+    #[must_use]
+    pub fn subscribe(&self, filter: EventFilter) -> Receiver<SimulationEvent> {
+        self.subscribers.subscribe(filter)
+    }
+
+    /// Applies a command on the controller thread and waits for its outcome
+    ///
+    /// # Errors
+    /// Returns the error of the command
+    ///
+    /// # Panics
+    /// Panics if the controller thread is gone
+    pub fn execute(&self, command: ControllerCommand) -> Result<(), ControlError> {
+        let (reply, outcome) = unbounded();
+        self.request(Request::Command(command, reply));
+        outcome.recv().expect("Controller thread is gone")
+    }
+
+    /// # Errors
+    /// See [`crate::control::NetworkHandle::crash_drone`]
+    pub fn crash_drone(&self, id: NodeId) -> Result<(), ControlError> {
+        self.execute(ControllerCommand::CrashDrone(id))
+    }
+
+    /// # Errors
+    /// See [`crate::control::NetworkHandle::set_pdr`]
+    pub fn set_pdr(&self, id: NodeId, pdr: f32) -> Result<(), ControlError> {
+        self.execute(ControllerCommand::SetPdr(id, pdr))
+    }
+
+    /// # Errors
+    /// See [`crate::control::NetworkHandle::add_link`]
+    pub fn add_link(&self, a: NodeId, b: NodeId) -> Result<(), ControlError> {
+        self.execute(ControllerCommand::AddLink(a, b))
+    }
+
+    /// # Errors
+    /// See [`crate::control::NetworkHandle::remove_link`]
+    pub fn remove_link(&self, a: NodeId, b: NodeId) -> Result<(), ControlError> {
+        self.execute(ControllerCommand::RemoveLink(a, b))
+    }
+
+    /// # Errors
+    /// Returns an error if `id` is not a client or a server, or it stopped
+    pub fn send_command(&self, id: NodeId, command: Box<dyn Command>) -> Result<(), ControlError> {
+        self.execute(ControllerCommand::SendToNode(id, command))
+    }
+
+    /// The network as it is now
+    ///
+    /// # Panics
+    /// Panics if the controller thread is gone
+    #[must_use]
+    pub fn network_view(&self) -> Network {
+        let (reply, view) = unbounded();
+        self.request(Request::NetworkView(reply));
+        view.recv().expect("Controller thread is gone")
+    }
+
+    /// Stops the simulation and the controller thread
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn request(&self, request: Request) {
+        self.requests
+            .as_ref()
+            .and_then(|requests| requests.send(request).ok())
+            .expect("Controller thread is gone");
+    }
+
+    fn shutdown(&mut self) {
+        // the loop stops the simulation when its inbox is disconnected
+        self.requests.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for SimulationController {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+struct ControllerLoop {
+    running: NetworkInitializer<Running>,
+    subscribers: Broadcaster,
+}
+
+impl ControllerLoop {
+    fn new(running: NetworkInitializer<Running>, subscribers: Broadcaster) -> Self {
+        Self {
+            running,
+            subscribers,
+        }
+    }
+
+    fn run(mut self, inbox: &Receiver<Request>) {
+        let mut events = self.running.subscribe(EventFilter::all());
+        loop {
+            select! {
+                recv(events) -> event => match event {
+                    Ok(event) => self.subscribers.publish(&event),
+                    // every node stopped, commands are still answered
+                    Err(_) => events = never(),
+                },
+                recv(inbox) -> request => match request {
+                    Ok(request) => self.handle(request),
+                    Err(_) => break,
+                },
+            }
+        }
+        self.running.stop_simulation();
+        self.subscribers.close();
+    }
+
+    fn handle(&mut self, request: Request) {
+        match request {
+            Request::Command(command, reply) => {
+                let _ = reply.send(self.apply(command));
+            }
+            Request::NetworkView(reply) => {
+                let _ = reply.send(self.running.get_network_view());
+            }
+        }
+    }
+
+    fn apply(&mut self, command: ControllerCommand) -> Result<(), ControlError> {
+        let network = self.running.get_network_handle();
+        match command {
+            ControllerCommand::CrashDrone(id) => network.crash_drone(id),
+            ControllerCommand::SetPdr(id, pdr) => network.set_pdr(id, pdr),
+            ControllerCommand::AddLink(a, b) => network.add_link(a, b),
+            ControllerCommand::RemoveLink(a, b) => network.remove_link(a, b),
+            ControllerCommand::SendToNode(id, command) => {
+                let (_, sender) = self
+                    .running
+                    .get_clients()
+                    .remove(&id)
+                    .or_else(|| self.running.get_servers().remove(&id))
+                    .ok_or(ControlError::UnknownNode(id))?;
+                sender
+                    .send(command)
+                    .map_err(|_| ControlError::ChannelClosed(id))
+            }
+            ControllerCommand::Partition(groups) => self.running.partition(&groups),
+            ControllerCommand::Heal => self.running.heal(),
+            ControllerCommand::Pause => {
+                self.running.pause();
+                Ok(())
+            }
+            ControllerCommand::Resume => {
+                self.running.resume();
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_initializer::Uninitialized;
+    use crate::query::EventQuery;
+    use crate::shortcut::ShortcutEvent;
+    use common::types::{WebCommand, WebEvent};
+    use std::time::Duration;
+    use wg_internal::network::SourceRoutingHeader;
+    use wg_internal::packet::{Ack, Packet, PacketType};
+
+    #[test]
+    fn test_simulation_controller() {
+        let running = NetworkInitializer::<Uninitialized>::new("./tests/correct_config.toml")
+            .with_shortcut_routing()
+            .initialize()
+            .start_simulation();
+        let drone_2 = running.get_comms_channels()[&2].get_sender();
+        let controller = SimulationController::start(running);
+        let events = controller.subscribe(EventFilter::all());
+
+        controller.set_pdr(2, 0.3).unwrap();
+        controller.crash_drone(1).unwrap();
+        assert_eq!(controller.crash_drone(4), Err(ControlError::NotADrone(4)));
+        controller
+            .send_command(4, Box::new(WebCommand::GetTextFilesList))
+            .unwrap();
+        // answered by the web browser
+        events
+            .wait_for(
+                |e: &WebEvent| {
+                    matches!(
+                        e,
+                        WebEvent::FilesLists {
+                            notification_from: 4,
+                            ..
+                        }
+                    )
+                },
+                Duration::from_secs(5),
+            )
+            .unwrap();
+        assert_eq!(
+            controller.send_command(2, Box::new(WebCommand::GetTextFilesList)),
+            Err(ControlError::UnknownNode(2))
+        );
+        controller.execute(ControllerCommand::Pause).unwrap();
+        controller.execute(ControllerCommand::Resume).unwrap();
+        let view = controller.network_view();
+        assert!(view.nodes.iter().all(|n| n.id != 1));
+
+        // drone 2 is not connected to client 5, the network delivers the ack
+        // and the controller only publishes the report
+        drone_2
+            .send(Packet {
+                routing_header: SourceRoutingHeader::new(vec![6, 2, 5], 1),
+                session_id: 0xACE,
+                pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+            })
+            .unwrap();
+        let report = events
+            .wait_for(|_: &ShortcutEvent| true, Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            report,
+            ShortcutEvent::Delivered {
+                to: 5,
+                session_id: 0xACE
+            }
+        );
+
+        controller.stop();
+        // the stream ends with the controller, the shortcut was reported once
+        let remaining: Vec<SimulationEvent> = events.iter().collect();
+        assert!(
+            remaining
+                .iter()
+                .all(|e| e.node_event::<ShortcutEvent>().is_none())
+        );
+    }
+}
@@ -0,0 +1,575 @@
+use crate::link::{Gate, LinkConfig, LossState, link_rng};
+use crossbeam::channel::{Receiver, Select, Sender, TryRecvError};
+use rand::rngs::StdRng;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use wg_internal::network::NodeId;
+use wg_internal::packet::Packet;
+
+/// How packets travel between nodes.
+///
+/// ```toml
+/// seed = 42
+///
+/// [execution]
+/// mode = "stepped"
+/// settle_ms = 10
+/// max_settle_ms = 1000
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionMode {
+    /// Every link forwards packets on its own thread, as soon as they are sent
+    #[default]
+    Threaded,
+    /// A single engine thread delivers one packet at a time, in a deterministic
+    /// order, waiting between two deliveries until every node thread handled
+    /// its input
+    Stepped,
+    /// Like `Stepped`, but packets are only delivered when the caller asks for
+    /// it with `step` or `run_until` on the running initializer
+    Manual,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExecutionConfig {
+    #[serde(default)]
+    pub mode: ExecutionMode,
+    // how long no node has to send anything before the network is settled,
+    // only where the state of the node threads cannot be read (no procfs)
+    #[serde(default = "default_settle_ms")]
+    pub settle_ms: u64,
+    // how long a node thread may stay busy before the engine stops waiting
+    // for it and falls back to `settle_ms` without packets
+    #[serde(default = "default_max_settle_ms")]
+    pub max_settle_ms: u64,
+}
+
+fn default_settle_ms() -> u64 {
+    10
+}
+
+fn default_max_settle_ms() -> u64 {
+    1000
+}
+
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        Self {
+            mode: ExecutionMode::default(),
+            settle_ms: default_settle_ms(),
+            max_settle_ms: default_max_settle_ms(),
+        }
+    }
+}
+
+/// A packet handed to its destination by the engine
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub from: NodeId,
+    pub to: NodeId,
+    pub packet: Packet,
+    // virtual time of the delivery, advanced by the link latencies
+    pub at: Duration,
+}
+
+// one direction of a link, packets wait in the engine queue instead of a thread
+struct Route {
+    config: LinkConfig,
+    input: Receiver<Packet>,
+    destination: Sender<Packet>,
+    rng: StdRng,
+    loss: LossState,
+    // packets taken from `input` so far
+    seq: u64,
+}
+
+// how often `settle` checks again whether the nodes are done
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+// how long the engine thread waits for a packet when the queue is empty
+const IDLE_TIMEOUT: Duration = Duration::from_millis(10);
+
+// (virtual delivery time, from, to, sequence number on the route)
+type QueueKey = (Duration, NodeId, NodeId, u64);
+
+#[derive(Default)]
+struct EngineState {
+    routes: BTreeMap<(NodeId, NodeId), Route>,
+    // inputs of the routes into nodes that left the network, drained and dropped
+    retired: Vec<Receiver<Packet>>,
+    queue: BTreeMap<QueueKey, Packet>,
+    now: Duration,
+}
+
+// a thread that sends packets on the routes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Task {
+    Node(NodeId),
+    // the thread delivering controller shortcuts
+    Shortcuts,
+}
+
+// procfs status file of each watched thread, `None` until the thread started
+#[derive(Default)]
+struct NodeThreads {
+    status: HashMap<Task, Option<PathBuf>>,
+    // a thread could not find its own status file
+    unreadable: bool,
+    // threads that kept `settle` waiting until its deadline, reported once
+    unsettled: HashSet<Task>,
+}
+
+/// Discrete-event executor: every packet sent on a link goes to a central queue,
+/// ordered by virtual delivery time and then by link, so that the same inputs
+/// always produce the same sequence of deliveries.
+#[derive(Clone)]
+pub(crate) struct Engine {
+    state: Arc<Mutex<EngineState>>,
+    threads: Arc<Mutex<NodeThreads>>,
+    seed: Option<u64>,
+    settle: Duration,
+    max_settle: Duration,
+}
+
+impl Engine {
+    pub fn new(seed: Option<u64>, config: &ExecutionConfig) -> Self {
+        Self {
+            state: Arc::default(),
+            threads: Arc::default(),
+            seed,
+            settle: Duration::from_millis(config.settle_ms),
+            max_settle: Duration::from_millis(config.max_settle_ms),
+        }
+    }
+
+    pub fn add_route(
+        &self,
+        from: NodeId,
+        to: NodeId,
+        config: LinkConfig,
+        input: Receiver<Packet>,
+        destination: Sender<Packet>,
+    ) {
+        let route = Route {
+            loss: LossState::new(config.loss.clone()),
+            rng: link_rng(self.seed, from, to),
+            config,
+            input,
+            destination,
+            seq: 0,
+        };
+        self.lock().routes.insert((from, to), route);
+    }
+
+    /// Forgets the route and its queued packets, releasing its destination
+    pub fn retire(&self, from: NodeId, to: NodeId) {
+        let mut state = self.lock();
+        if let Some(route) = state.routes.remove(&(from, to)) {
+            state.retired.push(route.input);
+        }
+        state
+            .queue
+            .retain(|(_, queued_from, queued_to, _), _| (*queued_from, *queued_to) != (from, to));
+    }
+
+    /// Wraps the body of the thread running node `id`, so `settle` can tell
+    /// whether the node is still handling its input
+    pub fn watched(
+        &self,
+        id: NodeId,
+        run: impl FnOnce() + Send + 'static,
+    ) -> impl FnOnce() + Send + 'static {
+        self.watch(Task::Node(id), run)
+    }
+
+    /// Like [`Self::watched`], for the thread delivering controller shortcuts
+    pub fn watched_shortcuts(
+        &self,
+        run: impl FnOnce() + Send + 'static,
+    ) -> impl FnOnce() + Send + 'static {
+        self.watch(Task::Shortcuts, run)
+    }
+
+    fn watch(
+        &self,
+        task: Task,
+        run: impl FnOnce() + Send + 'static,
+    ) -> impl FnOnce() + Send + 'static {
+        self.lock_threads().status.insert(task, None);
+        let threads = self.threads.clone();
+        move || {
+            {
+                let mut threads = threads.lock().unwrap_or_else(PoisonError::into_inner);
+                match std::fs::read_link("/proc/thread-self") {
+                    Ok(thread) => {
+                        let status = Path::new("/proc").join(thread).join("status");
+                        threads.status.insert(task, Some(status));
+                    }
+                    Err(_) => threads.unreadable = true,
+                }
+            }
+            run();
+        }
+    }
+
+    /// Moves the packets sent by the nodes into the queue, until every node
+    /// thread handled its input and no packet is left on the routes. A node
+    /// still busy after the maximum settle time is reported, and the engine
+    /// waits for a window without packets instead
+    pub fn settle(&self) {
+        if self.lock_threads().unreadable {
+            self.settle_for_window();
+            return;
+        }
+        let deadline = Instant::now() + self.max_settle;
+        loop {
+            self.collect();
+            if self.is_quiet() {
+                return;
+            }
+            if Instant::now() >= deadline {
+                self.report_unsettled();
+                self.settle_for_window();
+                return;
+            }
+            self.wait_for_input(POLL_INTERVAL);
+        }
+    }
+
+    fn report_unsettled(&self) {
+        let Err(task) = self.sleeping_threads() else {
+            return;
+        };
+        if self.lock_threads().unsettled.insert(task) {
+            let thread = match task {
+                Task::Node(id) => format!("Node {id}"),
+                Task::Shortcuts => "The shortcut thread".to_string(),
+            };
+            eprintln!(
+                "{thread} did not settle within {:?}, waiting {:?} without packets instead",
+                self.max_settle, self.settle
+            );
+        }
+    }
+
+    // no watched thread ran between two reads of their scheduler state, and
+    // meanwhile every route was empty: a sleeping thread is woken as soon as
+    // a packet or a command is sent to it, so it has nothing left to handle
+    fn is_quiet(&self) -> bool {
+        let Ok(before) = self.sleeping_threads() else {
+            return false;
+        };
+        let empty = self.lock().routes.values().all(|r| r.input.is_empty());
+        empty && self.sleeping_threads() == Ok(before)
+    }
+
+    // context switches of every watched thread still alive, or the first one
+    // that is running
+    fn sleeping_threads(&self) -> Result<BTreeMap<Task, u64>, Task> {
+        let threads = self.lock_threads();
+        let mut switches = BTreeMap::new();
+        for (task, path) in &threads.status {
+            // still starting
+            let path = path.as_ref().ok_or(*task)?;
+            // the thread is gone
+            let Ok(status) = std::fs::read_to_string(path) else {
+                continue;
+            };
+            let (sleeping, count) = parse_status(&status);
+            if !sleeping {
+                return Err(*task);
+            }
+            switches.insert(*task, count);
+        }
+        Ok(switches)
+    }
+
+    // true if a packet arrived on a route before `timeout`
+    fn wait_for_input(&self, timeout: Duration) -> bool {
+        let inputs: Vec<Receiver<Packet>> = self
+            .lock()
+            .routes
+            .values()
+            .map(|r| r.input.clone())
+            .collect();
+        if inputs.is_empty() {
+            std::thread::sleep(timeout);
+            return false;
+        }
+        let mut select = Select::new();
+        for input in &inputs {
+            select.recv(input);
+        }
+        select.ready_timeout(timeout).is_ok()
+    }
+
+    // until no node sent anything for the settle time
+    fn settle_for_window(&self) {
+        while self.wait_for_input(self.settle) {
+            self.collect();
+        }
+    }
+
+    // takes every packet waiting on a route, in route order
+    fn collect(&self) {
+        let mut state = self.lock();
+        let EngineState {
+            routes,
+            retired,
+            queue,
+            now,
+        } = &mut *state;
+        for input in retired.iter() {
+            while input.try_recv().is_ok() {}
+        }
+        for ((from, to), route) in routes.iter_mut() {
+            while let Ok(packet) = route.input.try_recv() {
+                route.seq += 1;
+                if route.loss.drops(&mut route.rng) {
+                    continue;
+                }
+                let at = *now + route.config.sample_delay(&mut route.rng);
+                queue.insert((at, *from, *to, route.seq), packet);
+            }
+        }
+    }
+
+    /// Delivers the next packet of the queue, if any
+    pub fn step(&self) -> Option<Delivery> {
+        let mut state = self.lock();
+        let ((at, from, to, _), packet) = state.queue.pop_first()?;
+        state.now = state.now.max(at);
+        let delivery = Delivery {
+            from,
+            to,
+            packet: packet.clone(),
+            at: state.now,
+        };
+        if let Some(route) = state.routes.get(&(from, to)) {
+            // the destination may already be gone, nothing to do then
+            let _ = route.destination.send(packet);
+        }
+        Some(delivery)
+    }
+
+    /// Waits for the network to settle and delivers the next packet, if any
+    pub fn settle_and_step(&self) -> Option<Delivery> {
+        self.settle();
+        self.step()
+    }
+
+    pub fn pending(&self) -> usize {
+        self.lock().queue.len()
+    }
+
+    pub fn now(&self) -> Duration {
+        self.lock().now
+    }
+
+    /// Runs the engine on its own thread: settle, deliver one packet, repeat,
+    /// until `shutdown` is disconnected
+    pub fn spawn(&self, gate: Gate, shutdown: Receiver<()>) -> JoinHandle<()> {
+        let engine = self.clone();
+        std::thread::spawn(move || {
+            while matches!(shutdown.try_recv(), Err(TryRecvError::Empty)) {
+                if gate.wait_while_paused(&shutdown) && engine.settle_and_step().is_none() {
+                    // nothing to deliver until a node sends a packet
+                    engine.wait_for_input(IDLE_TIMEOUT);
+                }
+            }
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, EngineState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_threads(&self) -> MutexGuard<'_, NodeThreads> {
+        self.threads.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// whether a thread sleeps, and how many times it was switched out, from its
+// procfs status file
+fn parse_status(status: &str) -> (bool, u64) {
+    let mut sleeping = false;
+    let mut switches = 0;
+    for line in status.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key {
+            // "S (sleeping)", a thread waiting on a channel
+            "State" => sleeping = value.starts_with('S'),
+            "voluntary_ctxt_switches" | "nonvoluntary_ctxt_switches" => {
+                switches += value.parse::<u64>().unwrap_or_default();
+            }
+            _ => {}
+        }
+    }
+    (sleeping, switches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::LossModel;
+    use crate::utils::Channel;
+    use wg_internal::network::SourceRoutingHeader;
+    use wg_internal::packet::FloodRequest;
+
+    fn stepped(seed: u64) -> Engine {
+        Engine::new(
+            Some(seed),
+            &ExecutionConfig {
+                mode: ExecutionMode::Stepped,
+                settle_ms: 5,
+                max_settle_ms: 200,
+            },
+        )
+    }
+
+    fn lossy_link(a: NodeId, b: NodeId) -> LinkConfig {
+        LinkConfig {
+            a,
+            b,
+            latency_ms: 10,
+            jitter_ms: 5,
+            loss: Some(LossModel::Uniform { p: 0.5 }),
+        }
+    }
+
+    fn packet(session_id: u64) -> Packet {
+        Packet::new_flood_request(
+            SourceRoutingHeader::empty_route(),
+            session_id,
+            FloodRequest::new(session_id, 1),
+        )
+    }
+
+    // sends the same packets through two links and returns what the engine delivered
+    fn run(seed: u64) -> Vec<(NodeId, u64, Duration)> {
+        let engine = stepped(seed);
+        let destination = Channel::new();
+        let inputs: Vec<Channel<Packet>> = (0..2).map(|_| Channel::new()).collect();
+        for (from, input) in [3, 1].into_iter().zip(&inputs) {
+            engine.add_route(
+                from,
+                2,
+                lossy_link(from, 2),
+                input.get_receiver(),
+                destination.get_sender(),
+            );
+        }
+        for session_id in 0..20 {
+            inputs[0].send(packet(session_id)).unwrap();
+            inputs[1].send(packet(100 + session_id)).unwrap();
+        }
+
+        engine.settle();
+        std::iter::from_fn(|| engine.step())
+            .map(|d| (d.from, d.packet.session_id, d.at))
+            .collect()
+    }
+
+    // the node threads are watched through procfs
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_settle_waits_for_busy_nodes() {
+        let engine = stepped(1);
+        let link = |a, b| LinkConfig {
+            a,
+            b,
+            latency_ms: 0,
+            jitter_ms: 0,
+            loss: None,
+        };
+        let (to_node, at_node) = (Channel::new(), Channel::new());
+        let (from_node, back) = (Channel::new(), Channel::new());
+        engine.add_route(
+            1,
+            2,
+            link(1, 2),
+            to_node.get_receiver(),
+            at_node.get_sender(),
+        );
+        engine.add_route(
+            2,
+            1,
+            link(2, 1),
+            from_node.get_receiver(),
+            back.get_sender(),
+        );
+
+        // node 2 works on every packet far longer than the settle time, then answers
+        let (packets, answers) = (at_node.get_receiver(), from_node.get_sender());
+        let node = std::thread::spawn(engine.watched(2, move || {
+            for packet in packets {
+                let start = std::time::Instant::now();
+                while start.elapsed() < Duration::from_millis(100) {
+                    std::hint::spin_loop();
+                }
+                let _ = answers.send(packet);
+            }
+        }));
+
+        to_node.send(packet(1)).unwrap();
+        assert!(engine.settle_and_step().is_some_and(|d| d.to == 2));
+        engine.settle();
+        assert_eq!(engine.pending(), 1);
+        assert!(engine.step().is_some_and(|d| d.to == 1));
+
+        drop(at_node);
+        drop(engine);
+        node.join().unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_settle_gives_up_on_spinning_nodes() {
+        let engine = stepped(1);
+        let (to_node, at_node) = (Channel::new(), Channel::new());
+        engine.add_route(
+            1,
+            2,
+            LinkConfig::ideal(1, 2),
+            to_node.get_receiver(),
+            at_node.get_sender(),
+        );
+
+        // node 2 never sleeps
+        let spinning = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let node = std::thread::spawn(engine.watched(2, {
+            let spinning = spinning.clone();
+            move || {
+                while spinning.load(std::sync::atomic::Ordering::Relaxed) {
+                    std::hint::spin_loop();
+                }
+            }
+        }));
+
+        to_node.send(packet(1)).unwrap();
+        let start = Instant::now();
+        assert!(engine.settle_and_step().is_some_and(|d| d.to == 2));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(engine.lock_threads().unsettled.contains(&Task::Node(2)));
+
+        spinning.store(false, std::sync::atomic::Ordering::Relaxed);
+        node.join().unwrap();
+    }
+
+    #[test]
+    fn test_stepped_delivery_is_reproducible() {
+        let deliveries = run(42);
+        assert!(!deliveries.is_empty());
+        assert!(deliveries.len() < 40, "Half of the packets should be lost");
+        assert!(deliveries.windows(2).all(|w| w[0].2 <= w[1].2));
+        assert_eq!(deliveries, run(42));
+        assert_ne!(deliveries, run(7));
+    }
+}
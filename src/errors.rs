@@ -1,3 +1,6 @@
+use std::time::Duration;
+use wg_internal::network::NodeId;
+
 #[derive(Debug)]
 pub enum ConfigError {
     InvalidConfig(String),
@@ -8,6 +11,12 @@ pub enum ConfigError {
     DuplicateNodeId(String),
     InvalidPdrValue,
     UnidirectedConnection,
+    InvalidLink(String),
+    InvalidFault(String),
+    UnsupportedFormat(String),
+    WriteError(String),
+    UnknownImplementation { drone: NodeId, name: String },
+    InvalidScenario(String),
 }
 
 impl std::fmt::Display for ConfigError {
@@ -25,6 +34,14 @@ impl std::fmt::Display for ConfigError {
             ConfigError::UnidirectedConnection => {
                 write!(f, "Unidirected connection is not allowed.")
             }
+            ConfigError::InvalidLink(msg) => write!(f, "Invalid link: {msg}"),
+            ConfigError::InvalidFault(msg) => write!(f, "Invalid fault: {msg}"),
+            ConfigError::UnsupportedFormat(msg) => write!(f, "Unsupported config format: {msg}"),
+            ConfigError::WriteError(msg) => write!(f, "Cannot write configuration: {msg}"),
+            ConfigError::UnknownImplementation { drone, name } => {
+                write!(f, "Drone {drone} has an unknown implementation: {name}")
+            }
+            ConfigError::InvalidScenario(msg) => write!(f, "Invalid scenario: {msg}"),
         }
     }
 }
@@ -36,10 +53,22 @@ impl PartialEq<Self> for ConfigError {
             | (ConfigError::ParseError(msg1), ConfigError::ParseError(msg2))
             | (ConfigError::ConfigNotFound(msg1), ConfigError::ConfigNotFound(msg2))
             | (ConfigError::DuplicateNodeId(msg1), ConfigError::DuplicateNodeId(msg2))
+            | (ConfigError::InvalidLink(msg1), ConfigError::InvalidLink(msg2))
+            | (ConfigError::InvalidFault(msg1), ConfigError::InvalidFault(msg2))
+            | (ConfigError::UnsupportedFormat(msg1), ConfigError::UnsupportedFormat(msg2))
+            | (ConfigError::WriteError(msg1), ConfigError::WriteError(msg2))
+            | (ConfigError::InvalidScenario(msg1), ConfigError::InvalidScenario(msg2))
             | (
                 ConfigError::InvalidNodeConnection(msg1),
                 ConfigError::InvalidNodeConnection(msg2),
             ) => msg1 == msg2,
+            (
+                ConfigError::UnknownImplementation { drone, name },
+                ConfigError::UnknownImplementation {
+                    drone: drone2,
+                    name: name2,
+                },
+            ) => drone == drone2 && name == name2,
             (ConfigError::EmptyPath, ConfigError::EmptyPath)
             | (ConfigError::InvalidPdrValue, ConfigError::InvalidPdrValue)
             | (ConfigError::UnidirectedConnection, ConfigError::UnidirectedConnection) => true,
@@ -47,3 +76,60 @@ impl PartialEq<Self> for ConfigError {
         }
     }
 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlError {
+    UnknownNode(NodeId),
+    NotADrone(NodeId),
+    AlreadyConnected(NodeId, NodeId),
+    NotConnected(NodeId, NodeId),
+    InvalidPdrValue(f32),
+    ChannelClosed(NodeId),
+    NodeInMultipleGroups(NodeId),
+}
+
+impl std::fmt::Display for ControlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ControlError::UnknownNode(id) => write!(f, "Node {id} is not in the network"),
+            ControlError::NotADrone(id) => write!(f, "Node {id} is not a drone"),
+            ControlError::AlreadyConnected(a, b) => {
+                write!(f, "Nodes {a} and {b} are already connected")
+            }
+            ControlError::NotConnected(a, b) => write!(f, "Nodes {a} and {b} are not connected"),
+            ControlError::InvalidPdrValue(pdr) => write!(f, "Invalid PDR value {pdr}"),
+            ControlError::ChannelClosed(id) => write!(f, "Command channel of node {id} is closed"),
+            ControlError::NodeInMultipleGroups(id) => {
+                write!(f, "Node {id} belongs to more than one group")
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EventError {
+    // no matching event in time, with every event received meanwhile
+    Timeout(Duration, Vec<String>),
+    // the event channel closed before a matching event
+    Disconnected(Vec<String>),
+}
+
+impl std::fmt::Display for EventError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let seen = match self {
+            EventError::Timeout(timeout, seen) => {
+                write!(f, "No matching event within {timeout:?}")?;
+                seen
+            }
+            EventError::Disconnected(seen) => {
+                write!(f, "Event channel closed before a matching event")?;
+                seen
+            }
+        };
+        write!(f, ", {} other event(s) received", seen.len())?;
+        for event in seen {
+            write!(f, "\n  {event}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+//! Renders a topology as a Graphviz DOT or Mermaid diagram. Drones are drawn
+//! as ellipses labelled with their PDR, clients as boxes and servers as
+//! cylinders; the implementation of a node, when known, is added to its label.
+
+use crate::control::config_links;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+use wg_internal::config::Config;
+use wg_internal::network::NodeId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dot" | "graphviz" => Ok(GraphFormat::Dot),
+            "mermaid" | "mmd" => Ok(GraphFormat::Mermaid),
+            other => Err(format!("Unknown graph format: {other}")),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Drone(f32),
+    Client,
+    Server,
+}
+
+struct Node<'a> {
+    id: NodeId,
+    kind: Kind,
+    implementation: Option<&'a str>,
+}
+
+impl Node<'_> {
+    fn label_lines(&self) -> Vec<String> {
+        let mut lines = vec![match self.kind {
+            Kind::Drone(_) => format!("Drone {}", self.id),
+            Kind::Client => format!("Client {}", self.id),
+            Kind::Server => format!("Server {}", self.id),
+        }];
+        if let Some(implementation) = self.implementation {
+            lines.push(implementation.to_string());
+        }
+        if let Kind::Drone(pdr) = self.kind {
+            lines.push(format!("pdr {pdr}"));
+        }
+        lines
+    }
+}
+
+fn nodes<'a>(config: &Config, implementations: &'a HashMap<NodeId, String>) -> Vec<Node<'a>> {
+    let kinds = config
+        .drone
+        .iter()
+        .map(|d| (d.id, Kind::Drone(d.pdr)))
+        .chain(config.client.iter().map(|c| (c.id, Kind::Client)))
+        .chain(config.server.iter().map(|s| (s.id, Kind::Server)));
+    let mut nodes: Vec<Node> = kinds
+        .map(|(id, kind)| Node {
+            id,
+            kind,
+            implementation: implementations.get(&id).map(String::as_str),
+        })
+        .collect();
+    nodes.sort_unstable_by_key(|n| n.id);
+    nodes
+}
+
+/// Renders `config` in the given format. `implementations` maps a node to the
+/// name of the type running it and may be empty
+#[must_use]
+pub fn render(
+    config: &Config,
+    implementations: &HashMap<NodeId, String>,
+    format: GraphFormat,
+) -> String {
+    match format {
+        GraphFormat::Dot => to_dot(config, implementations),
+        GraphFormat::Mermaid => to_mermaid(config, implementations),
+    }
+}
+
+#[must_use]
+pub fn to_dot(config: &Config, implementations: &HashMap<NodeId, String>) -> String {
+    let mut out = String::from("graph network {\n");
+    for node in nodes(config, implementations) {
+        let shape = match node.kind {
+            Kind::Drone(_) => "ellipse",
+            Kind::Client => "box",
+            Kind::Server => "cylinder",
+        };
+        let label = node.label_lines().join("\\n");
+        let _ = writeln!(out, "    {} [shape={shape}, label=\"{label}\"];", node.id);
+    }
+    for (a, b) in config_links(config) {
+        let _ = writeln!(out, "    {a} -- {b};");
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[must_use]
+pub fn to_mermaid(config: &Config, implementations: &HashMap<NodeId, String>) -> String {
+    let mut out = String::from("graph LR\n");
+    for node in nodes(config, implementations) {
+        let label = node.label_lines().join("<br/>");
+        let (open, close) = match node.kind {
+            Kind::Drone(_) => ("((", "))"),
+            Kind::Client => ("[", "]"),
+            Kind::Server => ("[(", ")]"),
+        };
+        let _ = writeln!(out, "    n{}{open}\"{label}\"{close}", node.id);
+    }
+    for (a, b) in config_links(config) {
+        let _ = writeln!(out, "    n{a} --- n{b}");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::topology::{Endpoints, double_chain};
+
+    fn chain() -> Config {
+        double_chain(
+            1,
+            Endpoints {
+                clients: 1,
+                servers: 1,
+                pdr: 0.25,
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_dot_export() {
+        let implementations = HashMap::from([(1, "RustDoIt".to_string())]);
+        let dot = to_dot(&chain(), &implementations);
+        assert!(dot.starts_with("graph network {\n"));
+        assert!(dot.contains("1 [shape=ellipse, label=\"Drone 1\\nRustDoIt\\npdr 0.25\"];"));
+        assert!(dot.contains("2 [shape=ellipse, label=\"Drone 2\\npdr 0.25\"];"));
+        assert!(dot.contains("3 [shape=box, label=\"Client 3\"];"));
+        assert!(dot.contains("4 [shape=cylinder, label=\"Server 4\"];"));
+        assert!(dot.contains("    1 -- 2;\n"));
+        assert_eq!(dot.matches(" -- ").count(), 4);
+    }
+
+    #[test]
+    fn test_mermaid_export() {
+        let mermaid = render(&chain(), &HashMap::new(), GraphFormat::Mermaid);
+        assert!(mermaid.starts_with("graph LR\n"));
+        assert!(mermaid.contains("n1((\"Drone 1<br/>pdr 0.25\"))"));
+        assert!(mermaid.contains("n3[\"Client 3\"]"));
+        assert!(mermaid.contains("n4[(\"Server 4\")]"));
+        assert!(mermaid.contains("    n2 --- n4\n"));
+        assert_eq!("Mermaid".parse(), Ok(GraphFormat::Mermaid));
+        assert!("svg".parse::<GraphFormat>().is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::control::NetworkHandle;
+use crate::errors::ControlError;
+use crate::utils::{FAULT_STREAM, derive_seed, deserialize_duration};
+use common::types::Event;
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::HashMap;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use wg_internal::network::NodeId;
+
+/// A change applied to the running network `at` some time after the start,
+/// delayed by a random amount up to `jitter` (drawn from the simulation seed).
+///
+/// ```toml
+/// [[fault]]
+/// at = "2s"
+/// jitter = "500ms"
+/// action = "crash"
+/// node = 3
+///
+/// [[fault]]
+/// at = "5s"
+/// action = "set_pdr"
+/// node = 2
+/// pdr = 0.5
+///
+/// [[fault]]
+/// at = "8s"
+/// action = "remove_link"
+/// a = 1
+/// b = 4
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fault {
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub at: Duration,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub jitter: Duration,
+    #[serde(flatten)]
+    pub action: FaultAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FaultAction {
+    Crash { node: NodeId },
+    SetPdr { node: NodeId, pdr: f32 },
+    AddLink { a: NodeId, b: NodeId },
+    RemoveLink { a: NodeId, b: NodeId },
+}
+
+impl FaultAction {
+    pub fn apply(&self, network: &NetworkHandle) -> Result<(), ControlError> {
+        match *self {
+            FaultAction::Crash { node } => network.crash_drone(node),
+            FaultAction::SetPdr { node, pdr } => network.set_pdr(node, pdr),
+            FaultAction::AddLink { a, b } => network.add_link(a, b),
+            FaultAction::RemoveLink { a, b } => network.remove_link(a, b),
+        }
+    }
+
+    /// The node the fault is reported by, the first one for links
+    #[must_use]
+    pub fn node(&self) -> NodeId {
+        match *self {
+            FaultAction::Crash { node } | FaultAction::SetPdr { node, .. } => node,
+            FaultAction::AddLink { a, .. } | FaultAction::RemoveLink { a, .. } => a,
+        }
+    }
+}
+
+/// A fault of the timeline was applied `at` some time after the start, with
+/// its outcome. Reported as a node event of [`FaultAction::node`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultInjected {
+    pub action: FaultAction,
+    pub at: Duration,
+    pub result: Result<(), ControlError>,
+}
+
+impl Event for FaultInjected {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Applies `faults` in time order, until all are done or `shutdown` is
+/// disconnected, and reports each one on the event channel in `reports` of
+/// the node it targets
+pub(crate) fn spawn_fault_scheduler(
+    faults: Vec<Fault>,
+    seed: Option<u64>,
+    network: NetworkHandle,
+    reports: HashMap<NodeId, Sender<Box<dyn Event>>>,
+    shutdown: Receiver<()>,
+) -> JoinHandle<()> {
+    let mut rng = seed.map_or_else(StdRng::from_os_rng, |seed| {
+        StdRng::seed_from_u64(derive_seed(seed, &[FAULT_STREAM]))
+    });
+    let mut timeline: Vec<(Duration, FaultAction)> = faults
+        .into_iter()
+        .map(|f| (f.at + rng.random_range(Duration::ZERO..=f.jitter), f.action))
+        .collect();
+    // stable, faults at the same time keep the config order
+    timeline.sort_by_key(|(at, _)| *at);
+
+    std::thread::spawn(move || {
+        let start = Instant::now();
+        for (at, action) in timeline {
+            let wait = (start + at).saturating_duration_since(Instant::now());
+            if !matches!(shutdown.recv_timeout(wait), Err(RecvTimeoutError::Timeout)) {
+                return;
+            }
+            let result = action.apply(&network);
+            if let Some(report) = reports.get(&action.node()) {
+                let _ = report.send(Box::new(FaultInjected { action, at, result }));
+            }
+        }
+    })
+}
@@ -0,0 +1,163 @@
+//! Config files can be written in TOML, JSON or YAML. The format is chosen by
+//! the file extension unless it is given explicitly, and every format goes
+//! through the same parsing and validation.
+
+use crate::errors::ConfigError;
+use crate::parser::{Parse, Validate};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::path::Path;
+use std::str::FromStr;
+use wg_internal::config::Config;
+use wg_internal::network::NodeId;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConfigFormat {
+    #[default]
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Format matching the extension of `path`
+    ///
+    /// # Errors
+    /// Returns [`ConfigError::UnsupportedFormat`] if `path` has no extension
+    /// or an unknown one
+    pub fn from_path(path: &str) -> Result<Self, ConfigError> {
+        Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .ok_or_else(|| ConfigError::UnsupportedFormat(format!("{path} has no extension")))?
+            .parse()
+    }
+
+    pub(crate) fn deserialize<T: DeserializeOwned>(self, content: &str) -> Result<T, ConfigError> {
+        match self {
+            ConfigFormat::Toml => toml::from_str(content).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::from_str(content).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_yaml_ng::from_str(content).map_err(|e| e.to_string()),
+        }
+        .map_err(ConfigError::ParseError)
+    }
+
+    fn serialize<T: Serialize>(self, value: &T) -> Result<String, ConfigError> {
+        match self {
+            ConfigFormat::Toml => toml::to_string(value).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::to_string_pretty(value).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_yaml_ng::to_string(value).map_err(|e| e.to_string()),
+        }
+        .map_err(ConfigError::WriteError)
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "toml" => Ok(ConfigFormat::Toml),
+            "json" => Ok(ConfigFormat::Json),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            other => Err(ConfigError::UnsupportedFormat(other.to_string())),
+        }
+    }
+}
+
+// same layout as the config files, so that they can be read back
+#[derive(Serialize)]
+struct ConfigFile<'a> {
+    drone: Vec<DroneEntry<'a>>,
+    client: Vec<EdgeEntry<'a>>,
+    server: Vec<EdgeEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct DroneEntry<'a> {
+    id: NodeId,
+    connected_node_ids: &'a [NodeId],
+    pdr: f32,
+}
+
+#[derive(Serialize)]
+struct EdgeEntry<'a> {
+    id: NodeId,
+    connected_drone_ids: &'a [NodeId],
+}
+
+impl<'a> From<&'a Config> for ConfigFile<'a> {
+    fn from(config: &'a Config) -> Self {
+        Self {
+            drone: config
+                .drone
+                .iter()
+                .map(|d| DroneEntry {
+                    id: d.id,
+                    connected_node_ids: &d.connected_node_ids,
+                    pdr: d.pdr,
+                })
+                .collect(),
+            client: config
+                .client
+                .iter()
+                .map(|c| EdgeEntry {
+                    id: c.id,
+                    connected_drone_ids: &c.connected_drone_ids,
+                })
+                .collect(),
+            server: config
+                .server
+                .iter()
+                .map(|s| EdgeEntry {
+                    id: s.id,
+                    connected_drone_ids: &s.connected_drone_ids,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Reads and validates a config file, in the given format or in the one
+/// matching its extension
+///
+/// # Errors
+/// Returns an error if the file cannot be read or parsed, or the config is not valid
+pub fn read_config(path: &str, format: Option<ConfigFormat>) -> Result<Config, ConfigError> {
+    let config = match format {
+        Some(format) => Config::parse_config_as(path, format)?,
+        None => Config::parse_config(path)?,
+    };
+    config.validate_config()?;
+    Ok(config)
+}
+
+/// Serializes a config to a string in the given format. Only the topology is
+/// written: the simulation settings of a config file (`seed`, `[link]`,
+/// `[[fault]]`, `[execution]`, ...) are not part of a [`Config`] and are lost
+///
+/// # Errors
+/// Returns an error if the config cannot be serialized
+pub fn config_to_string(config: &Config, format: ConfigFormat) -> Result<String, ConfigError> {
+    format.serialize(&ConfigFile::from(config))
+}
+
+/// Writes a config to `path`, in the given format or in the one matching its
+/// extension. Like [`config_to_string`], the simulation settings are not
+/// written: copy them over by hand to keep them in the new file
+///
+/// # Errors
+/// Returns an error if the extension of `path` is unknown and no format is
+/// given, the config cannot be serialized or the file cannot be written
+pub fn write_config(
+    config: &Config,
+    path: &str,
+    format: Option<ConfigFormat>,
+) -> Result<(), ConfigError> {
+    let format = match format {
+        Some(format) => format,
+        None => ConfigFormat::from_path(path)?,
+    };
+    let content = config_to_string(config, format)?;
+    std::fs::write(path, content).map_err(|e| ConfigError::WriteError(e.to_string()))
+}
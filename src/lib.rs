@@ -1,6 +1,23 @@
-mod errors;
+pub mod broadcast;
+pub mod cli;
+pub mod conformance;
+pub mod control;
+pub mod controller;
+pub mod engine;
+pub mod errors;
+pub mod export;
+pub mod fault;
+pub mod format;
+mod link;
 pub mod network_initializer;
 mod parser;
+pub mod query;
+pub mod repl;
+pub mod scenario;
+mod settings;
+pub mod shortcut;
+pub mod supervisor;
+pub mod topology;
 #[macro_use]
 mod utils;
 
@@ -16,15 +33,28 @@ mod tests {
         Receiver<Box<dyn Event>>,
     );
 
+    use crate::broadcast::EventFilter;
+    use crate::engine::ExecutionMode;
     use crate::errors::ConfigError;
+    use crate::errors::ControlError;
+    use crate::export::GraphFormat;
+    use crate::fault::{FaultAction, FaultInjected};
+    use crate::format::{ConfigFormat, read_config, write_config};
+    use crate::link::LossModel;
     use crate::network_initializer::NetworkInitializer;
     use crate::network_initializer::Running;
     use crate::network_initializer::Uninitialized;
     use crate::parser::Parse;
     use crate::parser::Validate;
+    use crate::parser::ValidateSettings;
+    use crate::query::EventQuery;
+    use crate::settings::SimulationSettings;
+    use crate::shortcut::ShortcutEvent;
+    use crate::supervisor::{NodeRestarted, RestartPolicy};
     use common::network::Network;
     use common::types::Event;
     use std::collections::HashMap;
+    use std::time::Duration;
     // use crate::utils::Channel;
     use common::types::NodeCommand;
     use common::types::NodeEvent;
@@ -36,7 +66,7 @@ mod tests {
     use wg_internal::config::Config;
     use wg_internal::controller::DroneCommand;
     use wg_internal::network::{NodeId, SourceRoutingHeader};
-    use wg_internal::packet::Packet;
+    use wg_internal::packet::{Ack, Packet, PacketType};
     // use wg_internal::packet::Packet;
 
     fn gen_simulation(path: &str) -> Simulation {
@@ -89,10 +119,110 @@ mod tests {
         assert_eq!(validation, Err(ConfigError::UnidirectedConnection));
     }
 
+    #[test]
+    fn test_config_formats() {
+        let toml = Config::parse_config("./tests/correct_config.toml").unwrap();
+        let json = read_config("./tests/correct_config.json", None).unwrap();
+        let yaml = read_config("./tests/correct_config.yaml", None).unwrap();
+        for config in [&json, &yaml] {
+            assert_eq!(format!("{config:?}"), format!("{toml:?}"));
+        }
+        assert_eq!(
+            read_config("./tests/unidirectional_error.json", None).unwrap_err(),
+            ConfigError::UnidirectedConnection
+        );
+        assert!(read_config("./tests/correct_config.json", Some(ConfigFormat::Toml)).is_err());
+        assert_eq!(
+            "xml".parse::<ConfigFormat>(),
+            Err(ConfigError::UnsupportedFormat("xml".to_string()))
+        );
+
+        assert_eq!(
+            ConfigFormat::from_path("./tests/test.txt"),
+            Err(ConfigError::UnsupportedFormat("txt".to_string()))
+        );
+        assert!(matches!(
+            read_config("./tests/test.txt", None),
+            Err(ConfigError::UnsupportedFormat(_))
+        ));
+
+        let dir = tempfile::tempdir().unwrap();
+        for ext in ["toml", "json", "yaml"] {
+            let path = dir.path().join(format!("config.{ext}"));
+            let path = path.to_str().unwrap();
+            write_config(&toml, path, None).unwrap();
+            let written = read_config(path, None).unwrap();
+            assert_eq!(format!("{written:?}"), format!("{toml:?}"), "{ext}");
+        }
+        assert!(matches!(
+            write_config(&toml, dir.path().join("config").to_str().unwrap(), None),
+            Err(ConfigError::UnsupportedFormat(_))
+        ));
+
+        let net_init = NetworkInitializer::<Uninitialized>::new("./tests/correct_config.yaml");
+        assert_eq!(net_init.config.drone.len(), 3);
+    }
+
+    #[test]
+    fn test_config_templates() {
+        let config = read_config("./tests/template_config.toml", None).unwrap();
+        let mut drones: Vec<(NodeId, f32)> = config.drone.iter().map(|d| (d.id, d.pdr)).collect();
+        drones.sort_by_key(|(id, _)| *id);
+        assert_eq!(
+            drones,
+            vec![(1, 0.1), (2, 0.2), (10, 0.2), (11, 0.2), (12, 0.2)],
+            "The defaults of each file apply to its own drones first"
+        );
+        let drone_2 = config.drone.iter().find(|d| d.id == 2).unwrap();
+        assert_eq!(drone_2.connected_node_ids, vec![1, 10, 11, 12, 21]);
+
+        let mut running = NetworkInitializer::<Uninitialized>::new("./tests/template_config.toml")
+            .initialize()
+            .start_simulation();
+        let implementations = running.get_drone_implementations();
+        assert_eq!(implementations[&1], "RustDrone");
+        assert_eq!(implementations[&11], "RustDrone");
+        assert_eq!(implementations[&2], "RustyDrone");
+        running.stop_simulation();
+
+        // a node table with the id of an included node updates it
+        let manual = read_config("./tests/manual_config.toml", None).unwrap();
+        assert_eq!(manual.drone.len(), 3);
+        assert!(manual.drone.iter().all(|d| d.pdr == 0.0));
+        assert_eq!(manual.drone[0].connected_node_ids, vec![2, 3, 5]);
+
+        let diamond = read_config("./tests/diamond_config.toml", None).unwrap();
+        assert_eq!(
+            format!("{diamond:?}"),
+            format!("{config:?}"),
+            "A file included twice is merged once"
+        );
+        assert_eq!(
+            Config::parse_config("./tests/include_cycle.toml").unwrap_err(),
+            ConfigError::InvalidConfig(
+                "./tests/include_cycle.toml is included by itself".to_string()
+            )
+        );
+        let invalid = read_config("./tests/invalid_implementation.toml", None).unwrap();
+        let settings =
+            SimulationSettings::parse_config("./tests/invalid_implementation.toml").unwrap();
+        assert_eq!(
+            settings.validate_settings(&invalid),
+            Err(ConfigError::UnknownImplementation {
+                drone: 10,
+                name: "PaperPlane".to_string()
+            })
+        );
+    }
+
     #[test]
     fn test_parsing_error() {
-        let config = Config::parse_config("./tests/invalid_config.toml");
-        assert!(config.is_err());
+        let Err(ConfigError::ParseError(error)) =
+            Config::parse_config("./tests/invalid_config.toml")
+        else {
+            panic!("Drone 2 has no connected_node_ids");
+        };
+        assert!(error.contains("line 6"), "{error}");
     }
 
     #[test]
@@ -107,6 +237,513 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_link_settings() {
+        let config = Config::parse_config("./tests/link_config.toml").unwrap();
+        let settings = SimulationSettings::parse_config("./tests/link_config.toml").unwrap();
+        assert_eq!(settings.link.len(), 2);
+        assert_eq!(settings.link[0].latency_ms, 20);
+        assert_eq!(settings.link[0].jitter_ms, 5);
+        assert_eq!(settings.link[1].jitter_ms, 0);
+        assert!(settings.link[0].loss.is_none());
+        assert!(matches!(
+            settings.link[1].loss,
+            Some(LossModel::GilbertElliott { loss_good, loss_bad, .. })
+                if loss_good == 0.0 && loss_bad == 0.9
+        ));
+        assert!(settings.validate_settings(&config).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_link() {
+        let config = Config::parse_config("./tests/invalid_link.toml").unwrap();
+        let settings = SimulationSettings::parse_config("./tests/invalid_link.toml").unwrap();
+        assert_eq!(
+            settings.validate_settings(&config),
+            Err(ConfigError::InvalidLink(
+                "Link 1-4 does not match any connection in the topology".to_string()
+            ))
+        );
+
+        let typo = toml::from_str::<SimulationSettings>("[[link]]\na = 1\nb = 2\nlatency = 20\n");
+        assert!(
+            typo.unwrap_err()
+                .to_string()
+                .contains("unknown field `latency`")
+        );
+        let settings = toml::from_str::<SimulationSettings>(&format!(
+            "[[link]]\na = 1\nb = 2\nlatency_ms = {}\njitter_ms = 1\n",
+            u64::MAX
+        ))
+        .unwrap();
+        assert_eq!(
+            settings.validate_settings(&config),
+            Err(ConfigError::InvalidLink(
+                "Link 1-2 has a latency plus jitter over 60000 ms".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_fault_settings() {
+        let config = Config::parse_config("./tests/fault_config.toml").unwrap();
+        let settings = SimulationSettings::parse_config("./tests/fault_config.toml").unwrap();
+        assert_eq!(settings.fault.len(), 3);
+        assert_eq!(settings.fault[1].at, Duration::from_millis(50));
+        assert!(matches!(
+            settings.fault[2].action,
+            FaultAction::RemoveLink { a: 3, b: 4 }
+        ));
+        assert!(settings.validate_settings(&config).is_ok());
+
+        let config = Config::parse_config("./tests/invalid_fault.toml").unwrap();
+        let settings = SimulationSettings::parse_config("./tests/invalid_fault.toml").unwrap();
+        assert_eq!(
+            settings.validate_settings(&config),
+            Err(ConfigError::InvalidFault(
+                "Node 4 is not a drone".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_fault_timeline() {
+        let mut running = NetworkInitializer::<Uninitialized>::new("./tests/fault_config.toml")
+            .initialize()
+            .start_simulation();
+        let events = running.get_nodes_event_receiver();
+
+        let mut injected = Vec::new();
+        while injected.len() < 3 {
+            let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+            if let Ok(event) = event.into_any().downcast::<FaultInjected>() {
+                injected.push(*event);
+            }
+        }
+        assert_eq!(
+            injected
+                .iter()
+                .map(|e| e.action.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                FaultAction::SetPdr { node: 2, pdr: 0.5 },
+                FaultAction::Crash { node: 1 },
+                FaultAction::RemoveLink { a: 3, b: 4 },
+            ]
+        );
+        assert!(injected.iter().all(|e| e.result.is_ok()));
+
+        let network = running.get_network_view();
+        let adjacents = |id: NodeId| {
+            network
+                .nodes
+                .iter()
+                .find(|n| n.id == id)
+                .map(|n| n.get_adjacents().clone())
+        };
+        assert!(adjacents(1).is_none(), "Drone 1 should have crashed");
+        assert_eq!(adjacents(5), Some(vec![]));
+        assert_eq!(adjacents(4), Some(vec![2]));
+        assert_eq!(
+            running
+                .get_network_handle()
+                .read_topology()
+                .drone
+                .iter()
+                .find(|d| d.id == 2)
+                .map(|d| d.pdr),
+            Some(0.5)
+        );
+
+        running.stop_simulation();
+    }
+
+    #[test]
+    fn test_partition_and_heal() {
+        let mut running = NetworkInitializer::<Uninitialized>::new("./tests/correct_config.toml")
+            .initialize()
+            .start_simulation();
+        let handle = running.get_network_handle();
+        let links = handle.links();
+
+        running.partition(&[vec![1, 5], vec![2, 3, 4, 6]]).unwrap();
+        assert!(!handle.links().contains(&(1, 2)));
+        assert!(!handle.links().contains(&(1, 3)));
+        assert!(handle.links().contains(&(1, 5)));
+        assert_eq!(handle.links().len(), links.len() - 2);
+
+        assert_eq!(
+            running.partition(&[vec![1], vec![1, 2]]),
+            Err(ControlError::NodeInMultipleGroups(1))
+        );
+
+        running.heal().unwrap();
+        assert_eq!(handle.links(), links);
+
+        running.stop_simulation();
+    }
+
+    #[test]
+    fn test_partition_rolls_back() {
+        let mut running = NetworkInitializer::<Uninitialized>::new("./tests/correct_config.toml")
+            .initialize()
+            .start_simulation();
+        let handle = running.get_network_handle();
+        let links = handle.links();
+
+        // server 6 stops taking commands, cutting 3-6 fails halfway
+        let (_, server) = running.get_servers().remove(&6).unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while server.send(Box::new(NodeCommand::Shutdown)).is_ok()
+            && std::time::Instant::now() < deadline
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            running.partition(&[vec![1, 2, 6], vec![3, 4, 5]]),
+            Err(ControlError::ChannelClosed(6))
+        );
+        let kept: Vec<_> = links.iter().filter(|(a, b)| *a != 6 && *b != 6).collect();
+        assert!(kept.iter().all(|link| handle.links().contains(link)));
+        // nothing was recorded as partitioned
+        running.heal().unwrap();
+        assert!(kept.iter().all(|link| handle.links().contains(link)));
+
+        running.stop_simulation();
+    }
+
+    #[test]
+    fn test_snapshot_config() {
+        let mut running = NetworkInitializer::<Uninitialized>::new("./tests/correct_config.toml")
+            .initialize()
+            .start_simulation();
+        let handle = running.get_network_handle();
+        handle.set_pdr(2, 0.5).unwrap();
+        handle.add_link(5, 2).unwrap();
+        handle.crash_drone(1).unwrap();
+
+        let snapshot = running.snapshot_config().unwrap();
+        assert_eq!(snapshot.drone.len(), 2);
+        assert!((snapshot.drone.iter().find(|d| d.id == 2).unwrap().pdr - 0.5).abs() < 1e-6);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.toml");
+        let path = path.to_str().unwrap();
+        write_config(&snapshot, path, None).unwrap();
+        let reloaded = NetworkInitializer::<Uninitialized>::new(path);
+        assert_eq!(format!("{:?}", reloaded.config), format!("{snapshot:?}"));
+
+        // server 6 is left with a single drone
+        handle.crash_drone(3).unwrap();
+        assert!(running.snapshot_config().is_err());
+
+        running.stop_simulation();
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let mut running = NetworkInitializer::<Uninitialized>::new("./tests/correct_config.toml")
+            .initialize()
+            .start_simulation();
+        assert!(!running.is_paused());
+        running.pause();
+        assert!(running.is_paused());
+        // commands still flow while packets are held
+        let drones = running.get_drones();
+        assert!(
+            drones[&1]
+                .1
+                .send(DroneCommand::SetPacketDropRate(0.1))
+                .is_ok()
+        );
+        running.resume();
+        assert!(!running.is_paused());
+        running.stop_simulation();
+    }
+
+    #[test]
+    fn test_stepped_settings() {
+        let settings = SimulationSettings::parse_config("./tests/stepped_config.toml").unwrap();
+        assert_eq!(settings.seed, Some(42));
+        assert_eq!(settings.execution.mode, ExecutionMode::Stepped);
+        assert_eq!(settings.execution.settle_ms, 5);
+
+        let settings = SimulationSettings::parse_config("./tests/correct_config.toml").unwrap();
+        assert_eq!(settings.seed, None);
+        assert_eq!(settings.execution.mode, ExecutionMode::Threaded);
+
+        let mut running = NetworkInitializer::<Uninitialized>::new("./tests/stepped_config.toml")
+            .initialize()
+            .start_simulation();
+        running.stop_simulation();
+    }
+
+    #[test]
+    fn test_shortcut_routing() {
+        let mut running = NetworkInitializer::<Uninitialized>::new("./tests/correct_config.toml")
+            .with_shortcut_routing()
+            .with_execution_mode(ExecutionMode::Manual)
+            .initialize()
+            .start_simulation();
+        let events = running.subscribe(EventFilter::all().with_nodes([1]));
+
+        // drone 1 is not connected to client 4, so it cannot forward the ack
+        let ack = Packet {
+            routing_header: SourceRoutingHeader::new(vec![6, 1, 4], 1),
+            session_id: 0xACE,
+            pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+        };
+        running.get_comms_channels()[&1].send(ack).unwrap();
+
+        // the shortcut is queued by the engine like any other packet
+        let delivery = running.run_until(|d| d.packet.session_id == 0xACE).unwrap();
+        assert_eq!((delivery.from, delivery.to), (1, 4));
+        assert_eq!(delivery.packet.routing_header.hop_index, 2);
+        let report = events
+            .wait_for(|_: &ShortcutEvent| true, Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            report,
+            ShortcutEvent::Delivered {
+                to: 4,
+                session_id: 0xACE
+            }
+        );
+
+        // the router stops and joins with the other helper threads
+        running.stop_simulation();
+    }
+
+    #[test]
+    fn test_restart_policy() {
+        let mut running = NetworkInitializer::<Uninitialized>::new("./tests/correct_config.toml")
+            .with_restart_policy(RestartPolicy::Always, 1)
+            .initialize()
+            .start_simulation();
+        let events = running.subscribe(EventFilter::all().with_nodes([1]));
+        let handle = running.get_network_handle();
+        let links = handle.links();
+
+        handle.crash_drone(1).unwrap();
+        let restarted = events
+            .wait_for(|_: &NodeRestarted| true, Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(restarted.restarts, 1);
+        assert_eq!(handle.links().len(), links.len());
+        assert!(links.iter().all(|link| handle.links().contains(link)));
+
+        // no restart left, the crash reaches the new command channel
+        handle.crash_drone(1).unwrap();
+        assert!(
+            events
+                .wait_for(|_: &NodeRestarted| true, Duration::from_millis(200))
+                .is_err()
+        );
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !running.node_status()[&1].finished && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(running.node_status()[&1].finished);
+
+        running.stop_simulation();
+    }
+
+    #[test]
+    fn test_restart_keeps_link_settings() {
+        let mut running = NetworkInitializer::<Uninitialized>::new("./tests/link_config.toml")
+            .with_restart_policy(RestartPolicy::Always, 1)
+            .initialize()
+            .start_simulation();
+        let events = running.subscribe(EventFilter::all().with_nodes([1]));
+        let handle = running.get_network_handle();
+
+        handle.crash_drone(1).unwrap();
+        events
+            .wait_for(|_: &NodeRestarted| true, Duration::from_secs(5))
+            .unwrap();
+        // the link into the new packet channel of drone 1 is a new one
+        for (from, to) in [(5, 1), (1, 5)] {
+            let link = handle.link_config(from, to).unwrap();
+            assert_eq!((link.latency_ms, link.jitter_ms), (20, 5), "{from}->{to}");
+        }
+        assert_eq!(handle.link_config(3, 1).unwrap().latency_ms, 0);
+
+        running.stop_simulation();
+    }
+
+    #[test]
+    fn test_node_status() {
+        let mut running = NetworkInitializer::<Uninitialized>::new("./tests/correct_config.toml")
+            .initialize()
+            .start_simulation();
+        let status = running.node_status();
+        assert_eq!(status.len(), 6);
+        assert!(status.values().all(|s| !s.finished && !s.panicked));
+        assert_eq!(status[&1].kind, wg_internal::packet::NodeType::Drone);
+        assert_eq!(
+            status[&1].implementation,
+            running.get_drone_implementations()[&1]
+        );
+        let (client_type, _) = running.get_clients()[&5];
+        assert_eq!(status[&5].implementation, format!("{client_type:?}"));
+
+        running.get_network_handle().crash_drone(1).unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !running.node_status()[&1].finished && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let status = running.node_status();
+        assert!(status[&1].finished && !status[&1].panicked);
+        assert!(!status[&2].finished);
+
+        running.stop_simulation();
+        assert!(running.node_status().values().all(|s| s.finished));
+    }
+
+    #[test]
+    fn test_node_status_after_stop() {
+        // drone 1 panics on its first packet
+        let mut running = NetworkInitializer::<Uninitialized>::new("./tests/panic_config.toml")
+            .initialize()
+            .start_simulation();
+        running.get_comms_channels()[&1]
+            .get_sender()
+            .send(Packet {
+                routing_header: SourceRoutingHeader::new(vec![5, 1], 1),
+                session_id: 0,
+                pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+            })
+            .unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !running.node_status()[&1].panicked && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let status = running.node_status();
+        assert!(status[&1].finished && status[&1].panicked);
+        assert!(!status[&2].panicked);
+
+        running.stop_simulation();
+        let status = running.node_status();
+        assert_eq!(status.len(), 6);
+        assert!(status.values().all(|s| s.finished));
+        assert!(status[&1].panicked);
+        assert_eq!(status[&1].implementation, "LockheedRustin");
+        assert!(status.iter().all(|(id, s)| *id == 1 || !s.panicked));
+        assert_eq!(status[&5].kind, wg_internal::packet::NodeType::Client);
+        assert_eq!(status[&5].implementation, "ChatClient");
+        assert_eq!(status[&6].implementation, "TextServer");
+    }
+
+    #[test]
+    fn test_seeded_node_types() {
+        let node_types = |seed: Option<u64>| {
+            let mut initializer =
+                NetworkInitializer::<Uninitialized>::new("./tests/correct_config.toml");
+            if let Some(seed) = seed {
+                initializer = initializer.with_seed(seed);
+            }
+            let mut running = initializer.initialize().start_simulation();
+            let mut types: Vec<_> = running
+                .get_clients()
+                .into_iter()
+                .chain(running.get_servers())
+                .map(|(id, (node_type, _))| (id, format!("{node_type:?}")))
+                .chain(
+                    running
+                        .get_drone_implementations()
+                        .into_iter()
+                        .map(|(id, name)| (id, name.to_string())),
+                )
+                .collect();
+            types.sort();
+            running.stop_simulation();
+            types
+        };
+        // the seed only drives the links, types follow the config order
+        let unseeded = node_types(None);
+        assert_eq!(node_types(Some(3)), unseeded);
+        assert_eq!(node_types(Some(4)), unseeded);
+        let name = |i| {
+            crate::utils::drone_implementation_name(crate::utils::default_drone_implementation(i))
+                .to_string()
+        };
+        assert_eq!(
+            unseeded,
+            vec![
+                (1, name(0)),
+                (2, name(1)),
+                (3, name(2)),
+                (4, "WebBrowser".to_string()),
+                (5, "ChatClient".to_string()),
+                (6, "TextServer".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_graph_export() {
+        let mut running = NetworkInitializer::<Uninitialized>::new("./tests/correct_config.toml")
+            .initialize()
+            .start_simulation();
+        let implementations = running.get_drone_implementations();
+        assert_eq!(implementations.len(), 3);
+
+        running.get_network_handle().set_pdr(1, 0.5).unwrap();
+        let dot = running.export_graph(GraphFormat::Dot);
+        assert!(dot.contains(&format!(
+            "1 [shape=ellipse, label=\"Drone 1\\n{}\\npdr 0.5\"];",
+            implementations[&1]
+        )));
+        assert!(dot.contains("6 [shape=cylinder"));
+        let mermaid = running.export_graph(GraphFormat::Mermaid);
+        assert!(mermaid.contains("n4[\"Client 4<br/>"));
+        assert_eq!(mermaid.matches(" --- ").count(), 8);
+
+        running.stop_simulation();
+    }
+
+    #[test]
+    fn test_manual_stepping() {
+        let mut running = NetworkInitializer::<Uninitialized>::new("./tests/manual_config.toml")
+            .initialize()
+            .start_simulation();
+        let is_endpoint = |id: NodeId| [4, 5, 6].contains(&id);
+
+        // the clients and the server flood 5 neighbors in total, one step
+        // hands a single request to a drone and leaves the others queued
+        let first = running.step().unwrap();
+        assert!(is_endpoint(first.from));
+        assert!(matches!(
+            first.packet.pack_type,
+            PacketType::FloodRequest(_)
+        ));
+        assert_eq!(running.pending_packets(), 4);
+
+        running.pause();
+        assert!(running.step().is_none());
+        assert_eq!(running.pending_packets(), 4);
+        running.resume();
+
+        let response = running
+            .run_until(|d| {
+                is_endpoint(d.to) && matches!(d.packet.pack_type, PacketType::FloodResponse(_))
+            })
+            .unwrap();
+        let mut deliveries = vec![first, response];
+        deliveries.extend(std::iter::from_fn(|| running.step()));
+        assert!(
+            deliveries.len() >= 20,
+            "Only {} deliveries",
+            deliveries.len()
+        );
+        assert!(deliveries.windows(2).all(|w| w[0].at <= w[1].at));
+        // the link latencies advance the virtual time
+        assert!(deliveries[deliveries.len() - 1].at > deliveries[0].at);
+        assert_eq!(running.virtual_time(), deliveries[deliveries.len() - 1].at);
+        assert_eq!(running.pending_packets(), 0);
+
+        running.stop_simulation();
+    }
+
     #[test]
     fn test_network_initializer() {
         let net_init = NetworkInitializer::<Uninitialized>::new("./tests/correct_config.toml");
@@ -133,7 +770,7 @@ mod tests {
 
         // Drones may be optional depending on config
         // but if present, check channels are usable
-        for (_, (_, tx)) in &drones {
+        for (_, tx) in drones.values() {
             assert!(
                 tx.send(wg_internal::controller::DroneCommand::Crash)
                     .is_ok()
@@ -255,168 +892,76 @@ mod tests {
         let config_path = "./config/simple_config.toml";
         let (running_sim, drones, clients, servers, network, event) = gen_simulation(config_path);
         let sender_client = &clients.get(&1).unwrap().1;
-        let _sender_server = &servers.get(&4).unwrap().1;
-
-        for _ in 0..2 {
-            let evt = event.recv().unwrap();
-            let evt = evt.into_any();
-
-            if let Some(evt) = evt.downcast_ref::<NodeEvent>() {
-                assert!(matches!(*evt, NodeEvent::FloodStarted { .. }));
-            } else {
-                panic!("No FloodStarted event received, received other event: {evt:?}");
-            }
-        }
-
-        for _ in 0..2 {
-            let evt = event.recv().unwrap();
-            let evt = evt.into_any();
-
-            if let Some(evt) = evt.downcast_ref::<NodeEvent>() {
-                assert!(matches!(*evt, NodeEvent::PacketSent { .. }));
-            } else {
-                panic!("No PacketSent event received, received other event: {evt:?}");
-            }
-        }
         let sender_server = &servers.get(&4).unwrap().1;
+        let timeout = Duration::from_secs(5);
+
+        // both endpoints flood the network when they start
+        let mut floods = 0;
+        event
+            .collect_until(
+                |e: &NodeEvent| {
+                    floods += usize::from(matches!(e, NodeEvent::FloodStarted { .. }));
+                    floods == 2
+                },
+                timeout,
+            )
+            .unwrap();
+
         let _result = sender_server.send(Box::new(WebCommand::AddTextFileFromPath(
             "./tests/non_existent.txt".to_string(),
         )));
-
-        let event_1 = event.recv().unwrap();
-
-        if let Ok(event_1) = event_1.into_any().downcast::<WebEvent>() {
-            assert!(matches!(*event_1, WebEvent::FileOperationError { .. }));
-        } else {
-            panic!("Not TextFileAdded, other event");
-        }
+        event
+            .wait_for(
+                |e: &WebEvent| matches!(e, WebEvent::FileOperationError { .. }),
+                timeout,
+            )
+            .unwrap();
 
         let _result = sender_server.send(Box::new(WebCommand::AddTextFileFromPath(
             "./tests/test.txt".to_string(),
         )));
-
-        let event_2 = event.recv().unwrap();
-        let event_2 = event_2.into_any();
-
-        if let Some(event_2) = event_2.downcast_ref::<WebEvent>() {
-            assert!(matches!(*event_2, WebEvent::TextFileAdded { .. }));
-        } else {
-            panic!("Not TextFileAdded, other event");
-        }
+        event
+            .wait_for(
+                |e: &WebEvent| matches!(e, WebEvent::TextFileAdded { .. }),
+                timeout,
+            )
+            .unwrap();
 
         // test event from client
         let _result = sender_client.send(Box::new(WebCommand::GetTextFilesList));
-        let event_3 = event.recv().unwrap();
-        let event_3 = event_3.into_any();
-        if let Ok(event_3) = event_3.downcast::<WebEvent>() {
-            assert!(
-                matches!(*event_3, WebEvent::FilesLists { .. }),
-                "Expected WebEvent::FilesLists but got {:?}",
-                *event_3
-            );
-            if let WebEvent::FilesLists {
-                notification_from,
-                files_map,
-            } = *event_3
-            {
-                assert!(notification_from == 1 && files_map.is_empty());
-            } else {
-                panic!("Expected WebEvent::FilesLists but got {:?}", *event_3);
-            }
-        } else {
-            panic!("Not TextFileAdded, other event");
+        let files_lists = event
+            .wait_for(
+                |e: &WebEvent| matches!(e, WebEvent::FilesLists { .. }),
+                timeout,
+            )
+            .unwrap();
+        if let WebEvent::FilesLists {
+            notification_from,
+            files_map,
+        } = files_lists
+        {
+            assert!(notification_from == 1 && files_map.is_empty());
         }
 
         let _result = sender_client.send(Box::new(WebCommand::QueryTextFilesList));
-        let event_4 = event.recv().unwrap();
-        let event_4 = event_4.into_any();
-
-        if let Ok(event_4) = event_4.downcast::<NodeEvent>() {
-            // è giusto che panici, arriva FloodStarted
-            assert!(
-                matches!(*event_4, NodeEvent::PacketSent { .. }),
-                "Expected NodeEvent::PacketSent but got {:?}",
-                *event_4
-            );
-        } else {
-            panic!("Not MessageSent, other event");
-        }
-
-        let event_5 = event.recv().unwrap();
-        let event_5 = event_5.into_any();
-
-        if let Ok(event_5) = event_5.downcast::<NodeEvent>() {
-            // è giusto che panici, arriva FloodStarted
-            assert!(
-                matches!(*event_5, NodeEvent::MessageSent { .. }),
-                "Expected NodeEvent::MessageSent but got {:?}",
-                *event_5
-            );
-        } else {
-            panic!("Not MessageSent, other event");
-        }
-
-        let event_6 = event.recv().unwrap();
-        let event_6 = event_6.into_any();
-
-        if let Ok(event_6) = event_6.downcast::<NodeEvent>() {
-            // è giusto che panici, arriva FloodStarted
-            assert!(
-                matches!(*event_6, NodeEvent::PacketSent { .. }),
-                "Expected NodeEvent::PacketSent but got {:?}",
-                *event_6
-            );
-        } else {
-            panic!("Not MessageSent, other event");
-        }
-        let event_7 = event.recv().unwrap();
-        let event_7 = event_7.into_any();
-
-        if let Ok(event_7) = event_7.downcast::<NodeEvent>() {
-            assert!(
-                matches!(*event_7, NodeEvent::MessageSent { .. }),
-                "Expected MessageSent::FilesLists but got {:?}",
-                *event_7
-            );
-        } else {
-            panic!("Not TextFileAdded, other event");
-        }
-
-        let event_8 = event.recv().unwrap();
-        let event_8 = event_8.into_any();
-        if let Ok(event_8) = event_8.downcast::<NodeEvent>() {
-            assert!(
-                matches!(*event_8, NodeEvent::PacketSent { .. }),
-                "Expected MessageSent::FilesLists but got {:?}",
-                *event_8
-            );
-        } else {
-            panic!("Not TextFileAdded, other event");
-        }
-
-        let event_9 = event.recv().unwrap();
-        let event_9 = event_9.into_any();
-        if let Ok(event_9) = event_9.downcast::<NodeEvent>() {
-            assert!(
-                matches!(*event_9, NodeEvent::MessageReceived { .. }),
-                "Expected MessageSent::FilesLists but got {:?}",
-                *event_9
-            );
-        } else {
-            panic!("Not TextFileAdded, other event");
-        }
-
-        let event_10 = event.recv().unwrap();
-        let event_10 = event_10.into_any();
-        if let Ok(event_10) = event_10.downcast::<NodeEvent>() {
-            assert!(
-                matches!(*event_10, NodeEvent::ServerTypeQueried { .. }),
-                "Expected NodeEvent::PacketSent but got {:?}",
-                *event_10
-            );
-        } else {
-            panic!("Not TextFileAdded, other event");
-        }
+        event
+            .wait_for(
+                |e: &NodeEvent| matches!(e, NodeEvent::MessageSent { .. }),
+                timeout,
+            )
+            .unwrap();
+        event
+            .wait_for(
+                |e: &NodeEvent| matches!(e, NodeEvent::MessageReceived { .. }),
+                timeout,
+            )
+            .unwrap();
+        event
+            .wait_for(
+                |e: &NodeEvent| matches!(e, NodeEvent::ServerTypeQueried { .. }),
+                timeout,
+            )
+            .unwrap();
 
         stop_simulation((running_sim, drones, clients, servers, network, event));
     }
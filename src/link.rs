@@ -0,0 +1,580 @@
+use crate::engine::{Engine, ExecutionConfig, ExecutionMode};
+use crate::utils::{Channel, LINK_STREAM, derive_seed};
+use crossbeam::channel::{Receiver, Sender, TryRecvError, never, select, unbounded};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use wg_internal::network::NodeId;
+use wg_internal::packet::Packet;
+
+// how long an idle or paused link waits before checking its state again
+const IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Longest delay a link can add to a packet, latency plus jitter
+pub const MAX_LINK_DELAY_MS: u64 = 60_000;
+
+/// Impairments of the link between two nodes, applied in both directions.
+///
+/// ```toml
+/// [[link]]
+/// a = 1
+/// b = 2
+/// latency_ms = 20
+/// jitter_ms = 5
+/// loss = { model = "gilbert_elliott", p = 0.05, r = 0.5, loss_bad = 0.9 }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkConfig {
+    pub a: NodeId,
+    pub b: NodeId,
+    #[serde(default)]
+    pub latency_ms: u64,
+    #[serde(default)]
+    pub jitter_ms: u64,
+    #[serde(default)]
+    pub loss: Option<LossModel>,
+}
+
+/// Packet loss on a link, independent of the `pdr` of the drones at its ends.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum LossModel {
+    /// Every packet is lost with probability `p`
+    Uniform { p: f64 },
+    /// Two-state burst loss: `p` is the chance to move from the good to the bad
+    /// state, `r` the chance to move back, and each state has its own loss rate
+    GilbertElliott {
+        p: f64,
+        r: f64,
+        #[serde(default)]
+        loss_good: f64,
+        #[serde(default = "default_loss_bad")]
+        loss_bad: f64,
+    },
+}
+
+fn default_loss_bad() -> f64 {
+    1.0
+}
+
+impl LossModel {
+    pub fn probabilities(&self) -> Vec<f64> {
+        match self {
+            LossModel::Uniform { p } => vec![*p],
+            LossModel::GilbertElliott {
+                p,
+                r,
+                loss_good,
+                loss_bad,
+            } => vec![*p, *r, *loss_good, *loss_bad],
+        }
+    }
+}
+
+// loss model of one direction, with the Gilbert-Elliott state
+pub(crate) struct LossState {
+    model: Option<LossModel>,
+    bad: bool,
+}
+
+impl LossState {
+    pub fn new(model: Option<LossModel>) -> Self {
+        Self { model, bad: false }
+    }
+
+    pub fn drops(&mut self, rng: &mut impl Rng) -> bool {
+        match self.model {
+            None => false,
+            Some(LossModel::Uniform { p }) => rng.random_bool(p),
+            Some(LossModel::GilbertElliott {
+                p,
+                r,
+                loss_good,
+                loss_bad,
+            }) => {
+                self.bad = if self.bad {
+                    !rng.random_bool(r)
+                } else {
+                    rng.random_bool(p)
+                };
+                rng.random_bool(if self.bad { loss_bad } else { loss_good })
+            }
+        }
+    }
+}
+
+impl LinkConfig {
+    // a link that delivers every packet instantly
+    pub(crate) fn ideal(a: NodeId, b: NodeId) -> Self {
+        Self {
+            a,
+            b,
+            latency_ms: 0,
+            jitter_ms: 0,
+            loss: None,
+        }
+    }
+
+    // latency +/- a uniform jitter, never negative; the sum saturates for
+    // values `validate_links` would reject
+    pub(crate) fn sample_delay(&self, rng: &mut impl Rng) -> Duration {
+        let offset = rng.random_range(0..=self.jitter_ms.saturating_mul(2));
+        Duration::from_millis(
+            self.latency_ms
+                .saturating_add(offset)
+                .saturating_sub(self.jitter_ms),
+        )
+    }
+}
+
+/// Random source of one direction of a link, reproducible when a seed is given
+pub(crate) fn link_rng(seed: Option<u64>, from: NodeId, to: NodeId) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(derive_seed(
+            seed,
+            &[LINK_STREAM, u64::from(from), u64::from(to)],
+        )),
+        None => StdRng::from_os_rng(),
+    }
+}
+
+// while paused, a channel whose sender is dropped on resume to wake the links
+type ResumeSignal = Option<(Sender<()>, Receiver<()>)>;
+
+/// Pauses packet delivery on every link sharing it
+#[derive(Clone, Default)]
+pub(crate) struct Gate {
+    paused: Arc<Mutex<ResumeSignal>>,
+}
+
+impl Gate {
+    pub fn pause(&self) {
+        let mut paused = self.lock();
+        if paused.is_none() {
+            *paused = Some(unbounded());
+        }
+    }
+
+    pub fn resume(&self) {
+        self.lock().take();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.lock().is_some()
+    }
+
+    /// Blocks while the gate is paused, false if `shutdown` disconnected meanwhile
+    pub fn wait_while_paused(&self, shutdown: &Receiver<()>) -> bool {
+        while let Some(resumed) = self.resumed() {
+            select! {
+                recv(resumed) -> _ => {}
+                recv(shutdown) -> _ => return false,
+            }
+        }
+        true
+    }
+
+    // disconnected as soon as the gate is resumed
+    fn resumed(&self) -> Option<Receiver<()>> {
+        self.lock().as_ref().map(|(_, resumed)| resumed.clone())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ResumeSignal> {
+        self.paused.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// sender into a link, and the one dropped to retire its thread
+struct LinkEntry {
+    input: Sender<Packet>,
+    retire: Option<Sender<()>>,
+    config: LinkConfig,
+}
+
+// (from, to) -> sender into the link
+type LinkSenders = HashMap<(NodeId, NodeId), LinkEntry>;
+
+/// Every link of the network, shared by the initializer and the network handle
+/// so links created at runtime are paused and stopped like the others
+#[derive(Clone)]
+pub(crate) struct Links {
+    senders: Arc<Mutex<LinkSenders>>,
+    // impairments of each direction given to `connect`, reused when the link
+    // is created again, e.g. for a restarted node or a healed partition
+    configured: Arc<Mutex<HashMap<(NodeId, NodeId), LinkConfig>>>,
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    gate: Gate,
+    // disconnected when the simulation stops
+    shutdown: Receiver<()>,
+    seed: Option<u64>,
+    // in stepped mode the engine delivers the packets instead of a thread per link
+    engine: Option<Engine>,
+}
+
+impl Links {
+    pub fn new(shutdown: Receiver<()>, seed: Option<u64>, execution: &ExecutionConfig) -> Self {
+        Self {
+            senders: Arc::default(),
+            configured: Arc::default(),
+            handles: Arc::default(),
+            gate: Gate::default(),
+            shutdown,
+            seed,
+            engine: (execution.mode != ExecutionMode::Threaded)
+                .then(|| Engine::new(seed, execution)),
+        }
+    }
+
+    /// Sender used by `from` to reach `to`, starting the link if it does not exist yet.
+    /// Without a `config`, the link gets the one given for the same direction
+    /// before, if any
+    pub fn connect(
+        &self,
+        from: NodeId,
+        to: NodeId,
+        config: Option<LinkConfig>,
+        destination: Sender<Packet>,
+    ) -> Sender<Packet> {
+        let config = {
+            let mut configured = self
+                .configured
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            match config {
+                Some(config) => configured
+                    .entry((from, to))
+                    .insert_entry(config)
+                    .get()
+                    .clone(),
+                None => configured
+                    .get(&(from, to))
+                    .cloned()
+                    .unwrap_or_else(|| LinkConfig::ideal(from, to)),
+            }
+        };
+        let mut senders = self.senders.lock().unwrap_or_else(PoisonError::into_inner);
+        senders
+            .entry((from, to))
+            .or_insert_with(|| {
+                let input = Channel::new();
+                if let Some(engine) = &self.engine {
+                    engine.add_route(from, to, config.clone(), input.get_receiver(), destination);
+                    return LinkEntry {
+                        input: input.get_sender(),
+                        retire: None,
+                        config,
+                    };
+                }
+                let retire = Channel::new();
+                let link = Link {
+                    config: config.clone(),
+                    input: input.get_receiver(),
+                    destination,
+                    gate: self.gate.clone(),
+                    rng: link_rng(self.seed, from, to),
+                    retired: retire.get_receiver(),
+                };
+                let shutdown = self.shutdown.clone();
+                self.spawned(std::thread::spawn(move || link.forward(&shutdown)));
+                LinkEntry {
+                    input: input.get_sender(),
+                    retire: Some(retire.get_sender()),
+                    config,
+                }
+            })
+            .input
+            .clone()
+    }
+
+    /// Impairments of the running link from `from` to `to`
+    pub fn config(&self, from: NodeId, to: NodeId) -> Option<LinkConfig> {
+        self.senders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&(from, to))
+            .map(|entry| entry.config.clone())
+    }
+
+    /// Retires every link into `to`: they drop the packets sent into them and
+    /// release their sender to `to`, so a node waiting for its packet channel
+    /// to disconnect can stop. Links created later by `connect` work again
+    pub fn disconnect(&self, to: NodeId) {
+        let mut senders = self.senders.lock().unwrap_or_else(PoisonError::into_inner);
+        let retired: Vec<(NodeId, NodeId)> =
+            senders.keys().filter(|(_, t)| *t == to).copied().collect();
+        for (from, to) in retired {
+            if let Some(entry) = senders.remove(&(from, to)) {
+                // wakes the link thread
+                drop(entry.retire);
+            }
+            if let Some(engine) = &self.engine {
+                engine.retire(from, to);
+            }
+        }
+    }
+
+    pub fn gate(&self) -> &Gate {
+        &self.gate
+    }
+
+    pub fn shutdown_receiver(&self) -> Receiver<()> {
+        self.shutdown.clone()
+    }
+
+    /// Starts delivering packets in stepped mode, nothing to do otherwise
+    pub fn start(&self, mode: ExecutionMode) {
+        if let Some(engine) = self
+            .engine
+            .as_ref()
+            .filter(|_| mode == ExecutionMode::Stepped)
+        {
+            self.spawned(engine.spawn(self.gate.clone(), self.shutdown.clone()));
+        }
+    }
+
+    pub fn engine(&self) -> Option<&Engine> {
+        self.engine.as_ref()
+    }
+
+    /// Body of the thread running node `id`, watched by the engine if any
+    pub fn watched(
+        &self,
+        id: NodeId,
+        run: impl FnOnce() + Send + 'static,
+    ) -> Box<dyn FnOnce() + Send> {
+        match &self.engine {
+            Some(engine) => Box::new(engine.watched(id, run)),
+            None => Box::new(run),
+        }
+    }
+
+    /// Body of the thread delivering controller shortcuts, watched by the
+    /// engine if any
+    pub fn watched_shortcuts(
+        &self,
+        run: impl FnOnce() + Send + 'static,
+    ) -> Box<dyn FnOnce() + Send> {
+        match &self.engine {
+            Some(engine) => Box::new(engine.watched_shortcuts(run)),
+            None => Box::new(run),
+        }
+    }
+
+    fn spawned(&self, handle: JoinHandle<()>) {
+        self.handles
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(handle);
+    }
+
+    /// Waits for every link thread and the engine, once the shutdown channel is disconnected
+    pub fn join(&self) {
+        let handles: Vec<_> = self
+            .handles
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain(..)
+            .collect();
+        for handle in handles {
+            if let Err(e) = handle.join() {
+                eprintln!("Failed to join a link thread: {e:?}");
+            }
+        }
+    }
+}
+
+/// One direction of a link: drops the packets sent into `input` or delivers
+/// them to `destination` once their delay expired and the gate is open.
+struct Link {
+    config: LinkConfig,
+    input: Receiver<Packet>,
+    destination: Sender<Packet>,
+    gate: Gate,
+    rng: StdRng,
+    // disconnected when the destination leaves the network
+    retired: Receiver<()>,
+}
+
+impl Link {
+    fn forward(self, shutdown: &Receiver<()>) {
+        let Link {
+            config,
+            input,
+            destination,
+            gate,
+            mut rng,
+            mut retired,
+        } = self;
+        // dropped once retired, the input is still drained so the senders
+        // left at the neighbors keep working
+        let mut destination = Some(destination);
+
+        let mut loss = LossState::new(config.loss.clone());
+        // packets waiting for their delivery time, ordered by (deliver_at, arrival order)
+        let mut in_flight: BTreeMap<(Instant, u64), Packet> = BTreeMap::new();
+        let mut seq = 0u64;
+        let running = never();
+
+        loop {
+            let resumed = gate.resumed();
+            let timeout = match (&resumed, in_flight.first_key_value()) {
+                (None, Some(((at, _), _))) => at.saturating_duration_since(Instant::now()),
+                _ => IDLE_TIMEOUT,
+            };
+            select! {
+                recv(input) -> packet => match packet {
+                    Ok(_) if destination.is_none() || loss.drops(&mut rng) => {}
+                    Ok(packet) => {
+                        let deliver_at = Instant::now() + config.sample_delay(&mut rng);
+                        in_flight.insert((deliver_at, seq), packet);
+                        seq += 1;
+                    }
+                    Err(_) => break,
+                },
+                recv(shutdown) -> _ => break,
+                recv(retired) -> _ => {}
+                recv(resumed.as_ref().unwrap_or(&running)) -> _ => {}
+                default(timeout) => {}
+            }
+
+            // checked before any delivery, whichever channel woke the link
+            if retired.try_recv() == Err(TryRecvError::Disconnected) {
+                destination = None;
+                in_flight.clear();
+                retired = never();
+            }
+            if gate.is_paused() {
+                continue;
+            }
+            let now = Instant::now();
+            while in_flight
+                .first_key_value()
+                .is_some_and(|((at, _), _)| *at <= now)
+            {
+                if let Some((_, packet)) = in_flight.pop_first()
+                    && let Some(destination) = &destination
+                {
+                    // the destination may already be gone, nothing to do then
+                    let _ = destination.send(packet);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam::channel::RecvTimeoutError;
+    use wg_internal::network::SourceRoutingHeader;
+    use wg_internal::packet::FloodRequest;
+
+    fn flood_request() -> Packet {
+        Packet::new_flood_request(
+            SourceRoutingHeader::empty_route(),
+            1,
+            FloodRequest::new(1, 1),
+        )
+    }
+
+    #[test]
+    fn test_link_delays_packets() {
+        let destination = Channel::new();
+        let shutdown: Channel<()> = Channel::new();
+        let links = Links::new(shutdown.get_receiver(), None, &ExecutionConfig::default());
+        let sender = links.connect(
+            1,
+            2,
+            Some(LinkConfig {
+                a: 1,
+                b: 2,
+                latency_ms: 50,
+                jitter_ms: 10,
+                loss: None,
+            }),
+            destination.get_sender(),
+        );
+
+        let sent_at = Instant::now();
+        sender.send(flood_request()).unwrap();
+        assert!(destination.recv().is_ok());
+        assert!(sent_at.elapsed() >= Duration::from_millis(40));
+
+        drop(shutdown);
+        links.join();
+    }
+
+    #[test]
+    fn test_paused_links_hold_packets() {
+        let destination = Channel::new();
+        let shutdown: Channel<()> = Channel::new();
+        let links = Links::new(shutdown.get_receiver(), None, &ExecutionConfig::default());
+        let sender = links.connect(1, 2, None, destination.get_sender());
+
+        links.gate().pause();
+        sender.send(flood_request()).unwrap();
+        assert!(
+            destination
+                .receiver
+                .recv_timeout(Duration::from_millis(200))
+                .is_err()
+        );
+
+        links.gate().resume();
+        assert!(
+            destination
+                .receiver
+                .recv_timeout(Duration::from_millis(200))
+                .is_ok()
+        );
+
+        drop(shutdown);
+        links.join();
+    }
+
+    #[test]
+    fn test_disconnected_links_release_the_destination() {
+        let destination = Channel::new();
+        let shutdown: Channel<()> = Channel::new();
+        let links = Links::new(shutdown.get_receiver(), None, &ExecutionConfig::default());
+        let sender = links.connect(1, 2, None, destination.get_sender());
+        let receiver = destination.get_receiver();
+        drop(destination);
+
+        links.disconnect(2);
+        // the neighbor can still send, the packet is dropped
+        assert!(sender.send(flood_request()).is_ok());
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(1)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+
+        drop(shutdown);
+        links.join();
+    }
+
+    #[test]
+    fn test_loss_models() {
+        let mut rng = rand::rng();
+
+        let mut lossless = LossState::new(Some(LossModel::Uniform { p: 0.0 }));
+        let mut lossy = LossState::new(Some(LossModel::Uniform { p: 1.0 }));
+        assert!((0..100).all(|_| !lossless.drops(&mut rng)));
+        assert!((0..100).all(|_| lossy.drops(&mut rng)));
+
+        // always bad once entered, and the bad state loses everything
+        let mut burst = LossState::new(Some(LossModel::GilbertElliott {
+            p: 1.0,
+            r: 0.0,
+            loss_good: 0.0,
+            loss_bad: 1.0,
+        }));
+        assert!((0..100).all(|_| burst.drops(&mut rng)));
+        assert!(burst.bad);
+    }
+}
@@ -1,18 +1,33 @@
 // TODO: togliere
 #![allow(dead_code)]
 #![allow(unused_variables)]
-use crate::parser::{Parse, Validate};
-use crate::utils::{Channel, generate_drone};
-use client::chat_client::ChatClient;
-use client::web_browser::WebBrowser;
+use crate::broadcast::{
+    Broadcaster, EventFilter, EventSources, SimulationEvent, spawn_broadcaster,
+};
+use crate::control::{NetworkHandle, build_network_view};
+use crate::engine::{Delivery, Engine, ExecutionMode};
+use crate::errors::{ConfigError, ControlError};
+use crate::export::{GraphFormat, render};
+use crate::fault::spawn_fault_scheduler;
+use crate::format::{ConfigFormat, read_config};
+use crate::link::Links;
+use crate::parser::{Parse, Validate, ValidateSettings};
+use crate::settings::SimulationSettings;
+use crate::supervisor::{
+    Exit, NodeHandles, NodeRecord, NodeStatus, NodeThread, Recipe, RestartPolicy, Supervisor,
+    SupervisorHandle, lock_handles, spawn_isolated,
+};
+use crate::utils::{
+    Channel, client_type, drone_implementation_name, generate_drone, generate_node, server_type,
+};
 use common::Processor;
 use common::network::Network;
 use common::types::{Command, Event, NodeCommand, NodeType as CommonNodeType};
 use crossbeam::channel::{Receiver, Sender};
-use server::{ChatServer, MediaServer, TextServer};
 use std::collections::HashMap;
 use std::sync::{Arc, Barrier};
 use std::thread::JoinHandle;
+use std::time::Duration;
 use wg_internal::config::Config;
 use wg_internal::controller::{DroneCommand, DroneEvent};
 use wg_internal::drone::Drone;
@@ -26,55 +41,182 @@ pub struct Running;
 pub struct NetworkInitializer<State = Uninitialized> {
     // node_id, sender to that node
     communications_channels: HashMap<NodeId, Channel<Packet>>,
-    // each drone has his command receiver, controller needs senders to send commands
+    // each drone has his command receiver, controller needs senders to send
+    // commands. Moved to the `NetworkHandle` by `start_simulation`
     drone_command_channels: HashMap<NodeId, Sender<DroneCommand>>,
-    // each node has his command receiver, controller needs senders to send commands
+    // each node has his command receiver, controller needs senders to send
+    // commands. Moved to the `NetworkHandle` by `start_simulation`
     node_command_channels: HashMap<NodeId, (CommonNodeType, Sender<Box<dyn Command>>)>,
     // controller receives events from drones
     drone_event_channel: Channel<DroneEvent>,
+    // the broadcaster delivers `DroneEvent::ControllerShortcut` packets itself
+    shortcut_routing: bool,
     // controller receives events from nodes
     node_event_channel: Channel<Box<dyn Event>>,
+    // each drone and node reports on its own channel, read by the broadcaster
+    event_sources: EventSources,
+    // where each node thread reports its own panic
+    crash_reports: HashMap<NodeId, Sender<Box<dyn Event>>>,
+    // a panicking node is removed from the network like a crashed drone
+    panic_as_crash: bool,
+    restart_policy: RestartPolicy,
+    max_restarts: usize,
+    // what each node is restarted with
+    recipes: HashMap<NodeId, Recipe>,
+    // stopped before the nodes
+    supervisor: Option<SupervisorHandle>,
+    broadcaster: Broadcaster,
     total_nodes: usize,
     pub(crate) config: Config,
+    settings: SimulationSettings,
+    // every node reaches its neighbors through a link
+    links: Links,
+    // dropping it stops the link and fault scheduler threads
+    shutdown: Option<Sender<()>>,
+    service_handles: Vec<JoinHandle<()>>,
     // do not exists
     state: std::marker::PhantomData<State>,
 
     network_view: Option<Network>,
+    // live view of the running network, shared with the helper threads
+    network: Option<NetworkHandle>,
+    // links cut by `partition`, restored by `heal`
+    partitioned_links: Vec<(NodeId, NodeId)>,
 
     // these are needed to NetworkInitializer<Running> to run each node
     initialized_clients: HashMap<NodeId, Box<dyn Processor + Send>>,
     initialized_servers: HashMap<NodeId, Box<dyn Processor + Send>>,
     initialized_drones: HashMap<NodeId, Box<dyn Drone>>,
-
-    // to keep track of threads and join them at the end
-    node_handles: HashMap<NodeId, JoinHandle<()>>,
+    // name of the drone type chosen for each drone
+    drone_implementations: HashMap<NodeId, &'static str>,
+    // kind, implementation and exit of every node, kept once its thread is
+    // joined by `stop_simulation`
+    node_records: HashMap<NodeId, NodeRecord>,
+
+    // to keep track of threads and join them at the end, shared with the
+    // supervisor which replaces the ones of restarted nodes
+    node_handles: NodeHandles,
 }
 
 impl NetworkInitializer<Uninitialized> {
     /// # Panics
-    /// Panics it cannot parse the config or the config is not a valid config
+    /// Panics if the extension of the file is not a known format, it cannot
+    /// parse the config or the config is not a valid config
     #[must_use]
     pub fn new(config_path: &str) -> Self {
-        let config = Config::parse_config(config_path).expect("Failed to parse config");
-        config.validate_config().expect("Failed to validate config");
+        let format = ConfigFormat::from_path(config_path).expect("Unsupported config format");
+        Self::new_with_format(config_path, format)
+    }
+
+    /// Like [`Self::new`], reading the config in the given format whatever the
+    /// extension of the file
+    ///
+    /// # Panics
+    /// Panics it cannot parse the config or the config is not a valid config
+    #[must_use]
+    pub fn new_with_format(config_path: &str, format: ConfigFormat) -> Self {
+        let config = read_config(config_path, Some(format)).expect("Failed to parse config");
+        let settings = SimulationSettings::parse_config_as(config_path, format)
+            .expect("Failed to parse settings");
+        settings
+            .validate_settings(&config)
+            .expect("Failed to validate settings");
+        let shutdown = Channel::new();
         Self {
             communications_channels: HashMap::new(),
             drone_command_channels: HashMap::new(),
             node_command_channels: HashMap::new(),
             drone_event_channel: Channel::new(),
+            shortcut_routing: false,
             node_event_channel: Channel::new(),
+            event_sources: EventSources::default(),
+            crash_reports: HashMap::new(),
+            panic_as_crash: false,
+            restart_policy: RestartPolicy::Never,
+            max_restarts: 0,
+            recipes: HashMap::new(),
+            supervisor: None,
+            broadcaster: Broadcaster::new(),
             total_nodes: config.drone.len() + config.client.len() + config.server.len(),
             config,
+            links: Links::new(shutdown.get_receiver(), settings.seed, &settings.execution),
+            settings,
+            shutdown: Some(shutdown.get_sender()),
+            service_handles: Vec::new(),
             // do not exists
             state: std::marker::PhantomData,
             network_view: None,
+            network: None,
+            partitioned_links: Vec::new(),
             initialized_clients: HashMap::new(),
             initialized_servers: HashMap::new(),
             initialized_drones: HashMap::new(),
-            node_handles: HashMap::new(),
+            drone_implementations: HashMap::new(),
+            node_records: HashMap::new(),
+            node_handles: NodeHandles::default(),
         }
     }
 
+    /// Makes the run reproducible: link loss and fault jitter derive from
+    /// `seed`. Drone implementations and node types do not depend on it, they
+    /// cycle through the available ones in config order
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.settings.seed = Some(seed);
+        self.rebuild_links();
+        self
+    }
+
+    #[must_use]
+    pub fn with_execution_mode(mut self, mode: ExecutionMode) -> Self {
+        self.settings.execution.mode = mode;
+        self.rebuild_links();
+        self
+    }
+
+    /// Delivers the packets that drones hand to the controller with
+    /// `DroneEvent::ControllerShortcut` to the last hop of their routing
+    /// header, over a link from the drone like any other packet. Shortcuts
+    /// are then reported as
+    /// [`crate::shortcut::ShortcutEvent`]s on the node event channel instead of
+    /// being forwarded on the drone event channel
+    #[must_use]
+    pub fn with_shortcut_routing(mut self) -> Self {
+        self.shortcut_routing = true;
+        self
+    }
+
+    /// Removes a node whose thread panicked from the network, as if it had
+    /// crashed: its neighbors drop their sender to it. The panic is reported
+    /// as a [`crate::supervisor::NodeCrashed`] event either way
+    #[must_use]
+    pub fn with_panic_as_crash(mut self) -> Self {
+        self.panic_as_crash = true;
+        self
+    }
+
+    /// Starts the nodes whose thread stopped again, as allowed by `policy`, at
+    /// most `max_restarts` times each. A restarted node keeps its ID, gets
+    /// back the neighbors it had in the config that are still in the network
+    /// and new channels, which [`Self::get_drones`] and the [`NetworkHandle`]
+    /// return from then on, and is reported as a
+    /// [`crate::supervisor::NodeRestarted`] event
+    #[must_use]
+    pub fn with_restart_policy(mut self, policy: RestartPolicy, max_restarts: usize) -> Self {
+        self.restart_policy = policy;
+        self.max_restarts = max_restarts;
+        self
+    }
+
+    // no link exists before `initialize`, so they can still change mode and seed
+    fn rebuild_links(&mut self) {
+        self.links = Links::new(
+            self.links.shutdown_receiver(),
+            self.settings.seed,
+            &self.settings.execution,
+        );
+    }
+
     #[must_use]
     pub fn initialize(mut self) -> NetworkInitializer<Initialized> {
         self.initialize_channels();
@@ -91,20 +233,27 @@ impl NetworkInitializer<Uninitialized> {
             // initializing receiver channel of the drone
             if let Some(packet_receiver) = self.communications_channels.get(&d.id) {
                 let command_channel = Channel::new();
+                let event_channel = Channel::new();
                 let mut neighbors = HashMap::new();
                 for id in &d.connected_node_ids {
-                    if let Some(channel) = self.communications_channels.get(id) {
-                        neighbors.insert(*id, channel.get_sender());
+                    if let Some(sender) = self.neighbor_sender(d.id, *id) {
+                        neighbors.insert(*id, sender);
                     }
                 }
 
+                let implementation = self
+                    .settings
+                    .resolve_drone_implementation(i, d.id)
+                    .expect("Implementation checked by validate_settings");
+                self.drone_implementations
+                    .insert(d.id, drone_implementation_name(implementation));
                 self.drone_command_channels
                     .insert(d.id, command_channel.get_sender());
                 self.initialized_drones.insert(
                     d.id,
                     generate_drone(
-                        i,
-                        &self.drone_event_channel.sender,
+                        implementation,
+                        &event_channel.sender,
                         (
                             d.id,
                             command_channel.get_receiver(),
@@ -114,6 +263,22 @@ impl NetworkInitializer<Uninitialized> {
                         ),
                     ),
                 );
+                self.event_sources
+                    .drones
+                    .push((d.id, event_channel.get_receiver()));
+                self.recipes.insert(
+                    d.id,
+                    Recipe::Drone {
+                        implementation,
+                        events: event_channel.get_sender(),
+                    },
+                );
+                // drones only emit `DroneEvent`s, their panic is a node event
+                let crash_channel = Channel::new();
+                self.crash_reports.insert(d.id, crash_channel.get_sender());
+                self.event_sources
+                    .nodes
+                    .push((d.id, crash_channel.get_receiver()));
             }
         }
     }
@@ -124,40 +289,39 @@ impl NetworkInitializer<Uninitialized> {
             if let Some(packet_channel) = self.communications_channels.get(&c.id) {
                 let mut neighbors = HashMap::new();
                 c.connected_drone_ids.iter().for_each(|id| {
-                    if let Some(channel) = self.communications_channels.get(id) {
-                        neighbors.insert(*id, channel.get_sender());
+                    if let Some(sender) = self.neighbor_sender(c.id, *id) {
+                        neighbors.insert(*id, sender);
                     }
                 });
                 //create the channels
                 let command_channel = Channel::new();
-                #[allow(clippy::needless_late_init)]
-                let client: Box<dyn Processor>;
-                let node_type: CommonNodeType;
+                let event_channel = Channel::new();
                 // instantiate client
-                if idx == 0 {
-                    client = Box::new(WebBrowser::new(
-                        c.id,
-                        neighbors,
-                        packet_channel.get_receiver(),
-                        command_channel.get_receiver(),
-                        self.node_event_channel.get_sender(),
-                    ));
-                    node_type = CommonNodeType::WebBrowser;
-                } else {
-                    client = Box::new(ChatClient::new(
-                        c.id,
-                        neighbors,
-                        packet_channel.get_receiver(),
-                        command_channel.get_receiver(),
-                        self.node_event_channel.get_sender(),
-                    ));
-                    node_type = CommonNodeType::ChatClient;
-                }
+                let node_type = client_type(idx);
+                let client = generate_node(
+                    node_type,
+                    c.id,
+                    neighbors,
+                    packet_channel.get_receiver(),
+                    command_channel.get_receiver(),
+                    event_channel.get_sender(),
+                );
 
                 // save the channels
 
                 self.node_command_channels
                     .insert(c.id, (node_type, command_channel.get_sender()));
+                self.event_sources
+                    .nodes
+                    .push((c.id, event_channel.get_receiver()));
+                self.crash_reports.insert(c.id, event_channel.get_sender());
+                self.recipes.insert(
+                    c.id,
+                    Recipe::Node {
+                        node_type,
+                        events: event_channel.get_sender(),
+                    },
+                );
 
                 // save the client
                 self.initialized_clients.insert(c.id, client);
@@ -168,71 +332,46 @@ impl NetworkInitializer<Uninitialized> {
     fn initialize_servers(&mut self) {
         for (i, s) in self.config.server.iter().enumerate() {
             if let Some(packet_channel) = self.communications_channels.get(&s.id) {
-                let server: Box<dyn Processor>;
                 let mut neighbors = HashMap::new();
                 s.connected_drone_ids.iter().for_each(|id| {
-                    if let Some(channel) = self.communications_channels.get(id) {
-                        neighbors.insert(*id, channel.get_sender());
+                    if let Some(sender) = self.neighbor_sender(s.id, *id) {
+                        neighbors.insert(*id, sender);
                     }
                 });
-                let node_type: CommonNodeType;
 
                 let command_channel = Channel::new();
-
-                match i % 3 {
-                    0 => {
-                        server = Box::new(TextServer::new(
-                            s.id,
-                            neighbors.clone(),
-                            packet_channel.get_receiver(),
-                            command_channel.get_receiver(),
-                            self.node_event_channel.get_sender(),
-                        ));
-                        node_type = CommonNodeType::TextServer;
-                    }
-                    1 => {
-                        server = Box::new(MediaServer::new(
-                            s.id,
-                            neighbors.clone(),
-                            packet_channel.get_receiver(),
-                            command_channel.get_receiver(),
-                            self.node_event_channel.get_sender(),
-                        ));
-                        node_type = CommonNodeType::MediaServer;
-                    }
-                    2 => {
-                        server = Box::new(ChatServer::new(
-                            s.id,
-                            neighbors.clone(),
-                            packet_channel.get_receiver(),
-                            command_channel.get_receiver(),
-                            self.node_event_channel.get_sender(),
-                        ));
-                        node_type = CommonNodeType::ChatServer;
-                    }
-                    _ => unreachable!(),
-                }
+                let event_channel = Channel::new();
+
+                let node_type = server_type(i);
+                let server = generate_node(
+                    node_type,
+                    s.id,
+                    neighbors,
+                    packet_channel.get_receiver(),
+                    command_channel.get_receiver(),
+                    event_channel.get_sender(),
+                );
 
                 self.node_command_channels
                     .insert(s.id, (node_type, command_channel.get_sender()));
+                self.event_sources
+                    .nodes
+                    .push((s.id, event_channel.get_receiver()));
+                self.crash_reports.insert(s.id, event_channel.get_sender());
+                self.recipes.insert(
+                    s.id,
+                    Recipe::Node {
+                        node_type,
+                        events: event_channel.get_sender(),
+                    },
+                );
                 self.initialized_servers.insert(s.id, server);
             }
         }
     }
 
     fn inizialize_network_view(&mut self) {
-        let mut network = Network::default();
-        for d in &self.config.drone {
-            network.add_node_controller_view(d.id, NodeType::Drone, &d.connected_node_ids);
-        }
-        for c in &self.config.client {
-            network.add_node_controller_view(c.id, NodeType::Client, &c.connected_drone_ids);
-        }
-
-        for s in &self.config.server {
-            network.add_node_controller_view(s.id, NodeType::Server, &s.connected_drone_ids);
-        }
-        self.network_view = Some(network);
+        self.network_view = Some(build_network_view(&self.config));
     }
 
     fn initialize_channels(&mut self) {
@@ -246,6 +385,19 @@ impl NetworkInitializer<Uninitialized> {
             self.communications_channels.insert(s.id, Channel::new());
         }
     }
+
+    // packets to a neighbor go through the link between the two nodes
+    fn neighbor_sender(&self, from: NodeId, to: NodeId) -> Option<Sender<Packet>> {
+        let config = self
+            .settings
+            .link
+            .iter()
+            .find(|l| (l.a, l.b) == (from, to) || (l.a, l.b) == (to, from))
+            .cloned();
+        self.communications_channels
+            .get(&to)
+            .map(|channel| self.links.connect(from, to, config, channel.get_sender()))
+    }
 }
 
 impl NetworkInitializer<Initialized> {
@@ -255,41 +407,173 @@ impl NetworkInitializer<Initialized> {
             drone_command_channels: initializer.drone_command_channels,
             node_command_channels: initializer.node_command_channels,
             drone_event_channel: initializer.drone_event_channel,
+            shortcut_routing: initializer.shortcut_routing,
             node_event_channel: initializer.node_event_channel,
+            event_sources: initializer.event_sources,
+            crash_reports: initializer.crash_reports,
+            panic_as_crash: initializer.panic_as_crash,
+            restart_policy: initializer.restart_policy,
+            max_restarts: initializer.max_restarts,
+            recipes: initializer.recipes,
+            supervisor: initializer.supervisor,
+            broadcaster: initializer.broadcaster,
             total_nodes: initializer.total_nodes,
             config: initializer.config,
+            settings: initializer.settings,
+            links: initializer.links,
+            shutdown: initializer.shutdown,
+            service_handles: initializer.service_handles,
             state: std::marker::PhantomData,
             network_view: initializer.network_view,
+            network: initializer.network,
+            partitioned_links: initializer.partitioned_links,
             initialized_clients: initializer.initialized_clients,
             initialized_servers: initializer.initialized_servers,
             initialized_drones: initializer.initialized_drones,
-            node_handles: HashMap::new(),
+            drone_implementations: initializer.drone_implementations,
+            node_records: initializer.node_records,
+            node_handles: NodeHandles::default(),
         }
     }
 
+    /// Own receiver of every event matching `filter`, from the first one
+    /// emitted by the simulation. Unlike the shared receivers of
+    /// `get_nodes_event_receiver` and `get_drones_event_receiver`, subscribers
+    /// do not take events from each other
+    #[must_use]
+    pub fn subscribe(&self, filter: EventFilter) -> Receiver<SimulationEvent> {
+        self.broadcaster.subscribe(filter)
+    }
+
+    // kind and implementation of every node of the config
+    fn node_records(&self) -> HashMap<NodeId, NodeRecord> {
+        let drones = self.config.drone.iter().map(|d| {
+            let implementation = self.drone_implementations.get(&d.id).copied();
+            (
+                d.id,
+                NodeType::Drone,
+                implementation.unwrap_or_default().to_string(),
+            )
+        });
+        let clients = self
+            .config
+            .client
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (c.id, NodeType::Client, format!("{:?}", client_type(i))));
+        let servers = self
+            .config
+            .server
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.id, NodeType::Server, format!("{:?}", server_type(i))));
+        drones
+            .chain(clients)
+            .chain(servers)
+            .map(|(id, kind, implementation)| {
+                let record = NodeRecord {
+                    kind,
+                    implementation,
+                    panicked: false,
+                };
+                (id, record)
+            })
+            .collect()
+    }
+
     #[must_use]
     pub fn start_simulation(mut self) -> NetworkInitializer<Running> {
         let barrier = Arc::new(Barrier::new(self.total_nodes - self.config.drone.len()));
+        // from now on the handle keeps them, replaced when a node restarts
+        let network = NetworkHandle::new(
+            std::mem::take(&mut self.drone_command_channels),
+            std::mem::take(&mut self.node_command_channels),
+            &self.communications_channels,
+            self.links.clone(),
+            self.config.clone(),
+        );
+        if !self.settings.fault.is_empty() {
+            self.service_handles.push(spawn_fault_scheduler(
+                self.settings.fault.clone(),
+                self.settings.seed,
+                network.clone(),
+                self.crash_reports.clone(),
+                self.links.shutdown_receiver(),
+            ));
+        }
+        let on_panic = self.panic_as_crash.then(|| network.clone());
+        // ends with the last drone or node
+        self.service_handles.push(spawn_broadcaster(
+            std::mem::take(&mut self.event_sources),
+            self.broadcaster.clone(),
+            self.drone_event_channel.get_sender(),
+            self.node_event_channel.get_sender(),
+            self.shortcut_routing.then(|| network.clone()),
+        ));
+        let crash_reports = std::mem::take(&mut self.crash_reports);
+        let report = |id: &NodeId| {
+            crash_reports
+                .get(id)
+                .cloned()
+                .expect("Every node has a reporter")
+        };
+        let exits = Channel::new();
         for (id, mut drone) in self.initialized_drones.drain() {
-            let handle = std::thread::spawn(move || {
-                drone.run();
-            });
-            self.node_handles.insert(id, handle);
+            let thread = spawn_isolated(
+                id,
+                NodeType::Drone,
+                self.links.watched(id, move || drone.run()),
+                report(&id),
+                on_panic.clone(),
+                exits.get_sender(),
+            );
+            lock_handles(&self.node_handles).insert(id, thread);
         }
         for (id, mut client) in self.initialized_clients.drain() {
             let barrier = barrier.clone();
-            let handle = std::thread::spawn(move || {
-                client.run(barrier);
-            });
-            self.node_handles.insert(id, handle);
+            let thread = spawn_isolated(
+                id,
+                NodeType::Client,
+                self.links.watched(id, move || client.run(barrier)),
+                report(&id),
+                on_panic.clone(),
+                exits.get_sender(),
+            );
+            lock_handles(&self.node_handles).insert(id, thread);
         }
         for (id, mut server) in self.initialized_servers.drain() {
             let barrier = barrier.clone();
-            let handle = std::thread::spawn(move || {
-                server.run(barrier);
-            });
-            self.node_handles.insert(id, handle);
+            let thread = spawn_isolated(
+                id,
+                NodeType::Server,
+                self.links.watched(id, move || server.run(barrier)),
+                report(&id),
+                on_panic.clone(),
+                exits.get_sender(),
+            );
+            lock_handles(&self.node_handles).insert(id, thread);
+        }
+        // without a supervisor the reporters and recipes are dropped here, so
+        // the broadcaster still ends with the last node
+        let recipes = std::mem::take(&mut self.recipes);
+        if self.restart_policy != RestartPolicy::Never {
+            let supervisor = Supervisor {
+                policy: self.restart_policy,
+                max_restarts: self.max_restarts,
+                network: network.clone(),
+                config: self.config.clone(),
+                recipes,
+                reports: crash_reports,
+                panic_as_crash: self.panic_as_crash,
+                handles: self.node_handles.clone(),
+                links: self.links.clone(),
+                exits: exits.get_sender(),
+            };
+            self.supervisor = Some(supervisor.spawn(exits.get_receiver()));
         }
+        self.network = Some(network);
+        self.node_records = self.node_records();
+        self.links.start(self.settings.execution.mode);
         NetworkInitializer::<Running>::new(self)
     }
 }
@@ -309,7 +593,7 @@ impl NetworkInitializer<Running> {
             "Servers should have been moved"
         );
         assert!(
-            initializer.node_handles.len() == initializer.total_nodes,
+            lock_handles(&initializer.node_handles).len() == initializer.total_nodes,
             "All nodes should have been started"
         );
         assert!(
@@ -322,14 +606,31 @@ impl NetworkInitializer<Running> {
             drone_command_channels: initializer.drone_command_channels,
             node_command_channels: initializer.node_command_channels,
             drone_event_channel: initializer.drone_event_channel,
+            shortcut_routing: initializer.shortcut_routing,
             node_event_channel: initializer.node_event_channel,
+            event_sources: initializer.event_sources,
+            crash_reports: initializer.crash_reports,
+            panic_as_crash: initializer.panic_as_crash,
+            restart_policy: initializer.restart_policy,
+            max_restarts: initializer.max_restarts,
+            recipes: initializer.recipes,
+            supervisor: initializer.supervisor,
+            broadcaster: initializer.broadcaster,
             total_nodes: initializer.total_nodes,
             config: initializer.config,
+            settings: initializer.settings,
+            links: initializer.links,
+            shutdown: initializer.shutdown,
+            service_handles: initializer.service_handles,
             state: std::marker::PhantomData,
             network_view: initializer.network_view,
+            network: initializer.network,
+            partitioned_links: initializer.partitioned_links,
             initialized_clients: initializer.initialized_clients,
             initialized_servers: initializer.initialized_servers,
             initialized_drones: initializer.initialized_drones,
+            drone_implementations: initializer.drone_implementations,
+            node_records: initializer.node_records,
             node_handles: initializer.node_handles,
         }
     }
@@ -337,16 +638,27 @@ impl NetworkInitializer<Running> {
     /// # Panics
     /// Panics if it cannot join handle
     pub fn stop_simulation(&mut self) {
-        for (id, (node_type, channel)) in self.node_command_channels.drain() {
+        if let Some(supervisor) = self.supervisor.take()
+            && let Err(e) = supervisor.stop()
+        {
+            eprintln!("Failed to join the supervisor thread: {e:?}");
+        }
+        let network = self.get_network_handle();
+        for (id, (node_type, channel)) in network.node_command_senders() {
             if let Some(packet_sender) = self.communications_channels.remove(&id) {
                 drop(packet_sender);
             }
             let _ = channel.send(Box::new(NodeCommand::Shutdown));
-            match self.node_handles.remove(&id) {
-                Some(handle) => match handle.join() {
-                    Ok(()) => {
+            network.release_node(id);
+            let thread = lock_handles(&self.node_handles).remove(&id);
+            match thread.map(NodeThread::join) {
+                Some(exit) => match self.record_exit(id, exit) {
+                    Ok(Exit::Finished) => {
                         println!("Terminated a {node_type:?} thread successfully");
                     }
+                    Ok(Exit::Panicked) => {
+                        eprintln!("The {node_type:?} thread of node {id} had panicked");
+                    }
                     Err(e) => {
                         eprintln!("Failed to join a {node_type:?} thread: {e:?}");
                     }
@@ -356,16 +668,23 @@ impl NetworkInitializer<Running> {
                 }
             }
         }
-        for (id, channel) in self.drone_command_channels.drain() {
+        for (id, channel) in network.drone_command_senders() {
             if let Some(packet_sender) = self.communications_channels.remove(&id) {
                 drop(packet_sender);
             }
             let _ = channel.send(DroneCommand::Crash);
-            match self.node_handles.remove(&id) {
-                Some(handle) => match handle.join() {
-                    Ok(()) => {
+            // a drone may keep handling packets after `Crash` until its packet
+            // channel is disconnected
+            network.release_node(id);
+            let thread = lock_handles(&self.node_handles).remove(&id);
+            match thread.map(NodeThread::join) {
+                Some(exit) => match self.record_exit(id, exit) {
+                    Ok(Exit::Finished) => {
                         println!("Terminated a drone thread successfully");
                     }
+                    Ok(Exit::Panicked) => {
+                        eprintln!("The drone thread of drone {id} had panicked");
+                    }
                     Err(e) => {
                         eprintln!("Failed to join a drone thread: {e:?}");
                     }
@@ -375,8 +694,38 @@ impl NetworkInitializer<Running> {
                 }
             }
         }
+        // every node is gone, release the links and the fault scheduler
+        self.shutdown = None;
+        for handle in self.service_handles.drain(..) {
+            if let Err(e) = handle.join() {
+                eprintln!("Failed to join a service thread: {e:?}");
+            }
+        }
+        self.links.join();
+    }
+
+    // keeps whether the joined thread of `id` panicked for `node_status`
+    fn record_exit(
+        &mut self,
+        id: NodeId,
+        exit: std::thread::Result<Exit>,
+    ) -> std::thread::Result<Exit> {
+        if let Some(record) = self.node_records.get_mut(&id) {
+            record.panicked = !matches!(exit, Ok(Exit::Finished));
+        }
+        exit
+    }
+
+    /// Own receiver of every event matching `filter` emitted from now on,
+    /// disconnected once every drone and node has stopped
+    #[must_use]
+    pub fn subscribe(&self, filter: EventFilter) -> Receiver<SimulationEvent> {
+        self.broadcaster.subscribe(filter)
     }
 
+    /// Shared by all its clones: two consumers take events from each other,
+    /// see [`Self::subscribe`] for independent receivers. It always gets the
+    /// original events, subscribers get copies
     #[must_use]
     pub fn get_nodes_event_receiver(&self) -> Receiver<Box<dyn Event>> {
         self.node_event_channel.get_receiver()
@@ -389,9 +738,10 @@ impl NetworkInitializer<Running> {
 
     #[must_use]
     pub fn get_drones(&self) -> HashMap<NodeId, (f32, Sender<DroneCommand>)> {
+        let channels = self.get_network_handle().drone_command_senders();
         let mut map = HashMap::new();
         for d in &self.config.drone {
-            if let Some(channel) = self.drone_command_channels.get(&d.id) {
+            if let Some(channel) = channels.get(&d.id) {
                 map.insert(d.id, (d.pdr, channel.clone()));
             }
         }
@@ -400,9 +750,10 @@ impl NetworkInitializer<Running> {
 
     #[must_use]
     pub fn get_clients(&self) -> HashMap<NodeId, (CommonNodeType, Sender<Box<dyn Command>>)> {
+        let channels = self.get_network_handle().node_command_senders();
         let mut map = HashMap::new();
         for c in &self.config.client {
-            if let Some((node_type, channel)) = self.node_command_channels.get(&c.id) {
+            if let Some((node_type, channel)) = channels.get(&c.id) {
                 map.insert(c.id, (*node_type, channel.clone()));
             }
         }
@@ -411,9 +762,10 @@ impl NetworkInitializer<Running> {
 
     #[must_use]
     pub fn get_servers(&self) -> HashMap<NodeId, (CommonNodeType, Sender<Box<dyn Command>>)> {
+        let channels = self.get_network_handle().node_command_senders();
         let mut map = HashMap::new();
         for s in &self.config.server {
-            if let Some((node_type, channel)) = self.node_command_channels.get(&s.id) {
+            if let Some((node_type, channel)) = channels.get(&s.id) {
                 map.insert(s.id, (*node_type, channel.clone()));
             }
         }
@@ -424,9 +776,206 @@ impl NetworkInitializer<Running> {
     /// # Panics
     /// Panisce if not Initialized
     pub fn get_network_view(&self) -> Network {
-        self.network_view.clone().expect("Network not Initialized")
+        self.network
+            .as_ref()
+            .map(NetworkHandle::network_view)
+            .expect("Network not Initialized")
+    }
+
+    /// Handle to change the running network, e.g. crash drones or remove links.
+    /// Changes made through it are reflected by [`Self::get_network_view`]
+    ///
+    /// # Panics
+    /// Panics if not Initialized
+    #[must_use]
+    pub fn get_network_handle(&self) -> NetworkHandle {
+        self.network.clone().expect("Network not Initialized")
+    }
+
+    /// Config of the network as it is now: crashed drones are gone, links and
+    /// PDRs are the ones set at runtime. It can be written with
+    /// [`crate::format::write_config`] and loaded again as a new simulation.
+    ///
+    /// Only changes made through the [`NetworkHandle`] (and what uses it: the
+    /// controller, faults, partitions) are seen: commands sent directly on
+    /// the channels of [`Self::get_drones`] or [`Self::get_comms_channels`],
+    /// and nodes that stopped on their own or panicked, are not reflected
+    ///
+    /// # Errors
+    /// Returns an error if the live topology is not a valid config anymore,
+    /// e.g. a client lost every drone it was connected to
+    ///
+    /// # Panics
+    /// Panics if not Initialized
+    pub fn snapshot_config(&self) -> Result<Config, ConfigError> {
+        let network = self.network.as_ref().expect("Network not Initialized");
+        let config = network.read_topology().clone();
+        config.validate_config()?;
+        Ok(config)
+    }
+
+    /// Name of the drone type running each drone
+    #[must_use]
+    pub fn get_drone_implementations(&self) -> HashMap<NodeId, &'static str> {
+        self.drone_implementations.clone()
+    }
+
+    /// Health of every node of the config, crashed ones included: whether its
+    /// thread finished or panicked and when it last emitted an event, so
+    /// silent or dead nodes can be told apart. A restarted node reports on
+    /// its current thread, a stopped simulation on how each thread ended
+    #[must_use]
+    pub fn node_status(&self) -> HashMap<NodeId, NodeStatus> {
+        let threads = lock_handles(&self.node_handles);
+        self.node_records
+            .iter()
+            .map(|(id, record)| {
+                // joined by `stop_simulation`
+                let thread = threads.get(id);
+                let status = NodeStatus {
+                    kind: record.kind,
+                    implementation: record.implementation.clone(),
+                    finished: thread.is_none_or(|thread| thread.handle.is_finished()),
+                    panicked: thread.map_or(record.panicked, NodeThread::panicked),
+                    last_event: self.broadcaster.last_event(*id),
+                };
+                (*id, status)
+            })
+            .collect()
+    }
+
+    /// Renders the live topology, with the PDRs and links as they are now,
+    /// labelling drones with their implementation and clients and servers with
+    /// their type
+    ///
+    /// # Panics
+    /// Panics if not Initialized
+    #[must_use]
+    pub fn export_graph(&self, format: GraphFormat) -> String {
+        let network = self.network.as_ref().expect("Network not Initialized");
+        let implementations: HashMap<NodeId, String> = self
+            .drone_implementations
+            .iter()
+            .map(|(id, name)| (*id, (*name).to_string()))
+            .chain(
+                network
+                    .node_command_senders()
+                    .into_iter()
+                    .map(|(id, (kind, _))| (id, format!("{kind:?}"))),
+            )
+            .collect();
+        render(&network.read_topology(), &implementations, format)
+    }
+
+    /// Holds every packet on the links until [`Self::resume`], command and
+    /// event channels keep working so the network can be inspected meanwhile
+    pub fn pause(&self) {
+        self.links.gate().pause();
+    }
+
+    /// Delivers the packets held since [`Self::pause`] and lets new ones through
+    pub fn resume(&self) {
+        self.links.gate().resume();
+    }
+
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        self.links.gate().is_paused()
+    }
+
+    /// Waits for the nodes to settle and delivers the next queued packet.
+    /// Always `None` while paused or unless the execution mode is
+    /// [`ExecutionMode::Manual`]
+    pub fn step(&self) -> Option<Delivery> {
+        if self.is_paused() {
+            return None;
+        }
+        self.manual_engine().and_then(Engine::settle_and_step)
+    }
+
+    /// Steps until a delivery matches `predicate` and returns it, or `None` once
+    /// the network is idle with nothing left to deliver
+    pub fn run_until(&self, mut predicate: impl FnMut(&Delivery) -> bool) -> Option<Delivery> {
+        std::iter::from_fn(|| self.step()).find(|delivery| predicate(delivery))
+    }
+
+    /// Packets sent by the nodes and waiting in the engine queue (manual mode)
+    #[must_use]
+    pub fn pending_packets(&self) -> usize {
+        self.manual_engine().map_or(0, Engine::pending)
+    }
+
+    /// Virtual time of the last delivery, advanced by the link latencies (manual mode)
+    #[must_use]
+    pub fn virtual_time(&self) -> Duration {
+        self.manual_engine().map_or(Duration::ZERO, Engine::now)
+    }
+
+    fn manual_engine(&self) -> Option<&Engine> {
+        self.links
+            .engine()
+            .filter(|_| self.settings.execution.mode == ExecutionMode::Manual)
+    }
+
+    /// Cuts every link between nodes of different `groups`, the links of nodes
+    /// not listed in any group are left untouched
+    ///
+    /// # Errors
+    /// Returns an error if a node is unknown or listed in more than one group,
+    /// or if a link cannot be removed, in which case the links already cut
+    /// are restored
+    pub fn partition(&mut self, groups: &[Vec<NodeId>]) -> Result<(), ControlError> {
+        let network = self.get_network_handle();
+        let topology_ids: Vec<NodeId> = network.network_view().nodes.iter().map(|n| n.id).collect();
+
+        let mut group_of = HashMap::new();
+        for (group, ids) in groups.iter().enumerate() {
+            for id in ids {
+                if !topology_ids.contains(id) {
+                    return Err(ControlError::UnknownNode(*id));
+                }
+                if group_of.insert(*id, group).is_some() {
+                    return Err(ControlError::NodeInMultipleGroups(*id));
+                }
+            }
+        }
+
+        let mut cut = Vec::new();
+        for (a, b) in network.links() {
+            if let (Some(group_a), Some(group_b)) = (group_of.get(&a), group_of.get(&b))
+                && group_a != group_b
+            {
+                if let Err(e) = network.remove_link(a, b) {
+                    // put back the links cut so far, best effort
+                    for (a, b) in cut.into_iter().rev() {
+                        let _ = network.add_link(a, b);
+                    }
+                    return Err(e);
+                }
+                cut.push((a, b));
+            }
+        }
+        self.partitioned_links.extend(cut);
+        Ok(())
+    }
+
+    /// Restores every link cut by [`Self::partition`]
+    ///
+    /// # Errors
+    /// Returns an error if a link cannot be restored, e.g. because one of its
+    /// nodes crashed in the meantime. That link is forgotten, calling `heal`
+    /// again restores the remaining ones
+    pub fn heal(&mut self) -> Result<(), ControlError> {
+        let network = self.get_network_handle();
+        while let Some((a, b)) = self.partitioned_links.pop() {
+            network.add_link(a, b)?;
+        }
+        Ok(())
     }
 
+    /// Packet channels the nodes were started with: a node restarted by the
+    /// supervisor reads from a new one, which its neighbors reach through the
+    /// [`NetworkHandle`]
     #[must_use]
     pub fn get_comms_channels(&self) -> &HashMap<NodeId, Channel<Packet>> {
         &self.communications_channels
@@ -1,37 +1,304 @@
 use std::collections::{HashMap, HashSet};
-
-use crate::utils::NodeType;
+use std::path::{Path, PathBuf};
+
+use crate::broadcast::EVENT_KINDS;
+use crate::fault::FaultAction;
+use crate::format::ConfigFormat;
+use crate::link::MAX_LINK_DELAY_MS;
+use crate::scenario::{ScenarioStep, event_kind, node_command};
+use crate::settings::SimulationSettings;
+use crate::utils::{NodeType, check_command, drone_implementation_index, endpoint_types};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
 use wg_internal::config::Config;
 use wg_internal::network::NodeId;
 
 use crate::errors::ConfigError;
 
-pub trait Parse {
-    fn parse_config(path: &str) -> Result<Config, ConfigError>;
+pub trait Parse: Sized {
+    fn parse_config_as(path: &str, format: ConfigFormat) -> Result<Self, ConfigError>;
+
+    // the format is the one matching the extension of the file
+    fn parse_config(path: &str) -> Result<Self, ConfigError> {
+        Self::parse_config_as(path, ConfigFormat::from_path(path)?)
+    }
 }
 
-impl Parse for Config {
-    fn parse_config(path_str: &str) -> Result<Config, ConfigError> {
-        if path_str.is_empty() {
-            return Err(ConfigError::EmptyPath);
+fn read_config_file(path_str: &str) -> Result<String, ConfigError> {
+    if path_str.is_empty() {
+        return Err(ConfigError::EmptyPath);
+    }
+    let path = std::path::Path::new(path_str);
+    if !path.exists() {
+        return Err(ConfigError::ConfigNotFound(path_str.to_string()));
+    }
+
+    std::fs::read_to_string(path_str).map_err(|e| ConfigError::ParseError(e.to_string()))
+}
+
+// files on the path of includes being read, to find cycles, and every file
+// read so far, so that one reached twice (a diamond) is only merged once
+#[derive(Default)]
+struct Includes {
+    including: Vec<PathBuf>,
+    read: HashSet<PathBuf>,
+}
+
+// reads a config file with the files it includes merged in, before them. The
+// `[defaults]` of each file are applied to its own nodes first, so that they
+// do not override the ones of the files it includes
+fn read_document(
+    path_str: &str,
+    format: ConfigFormat,
+    files: &mut Includes,
+) -> Result<Value, ConfigError> {
+    let content = read_config_file(path_str)?;
+    let path = Path::new(path_str);
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| ConfigError::ParseError(e.to_string()))?;
+    if files.including.contains(&canonical) {
+        return Err(ConfigError::InvalidConfig(format!(
+            "{path_str} is included by itself"
+        )));
+    }
+    if !files.read.insert(canonical.clone()) {
+        return Ok(Value::Object(Map::new()));
+    }
+
+    let mut document: Value = format.deserialize(&content)?;
+    let Some(table) = document.as_object_mut() else {
+        return Err(ConfigError::ParseError(format!(
+            "{path_str} does not contain a table"
+        )));
+    };
+    let defaults = table.get("defaults").cloned().unwrap_or_default();
+    expand_entries(table, &defaults)?;
+    let includes = match table.remove("include") {
+        None => Vec::new(),
+        Some(Value::Array(includes)) => includes,
+        Some(_) => {
+            return Err(ConfigError::InvalidConfig(
+                "`include` must be a list of paths".to_string(),
+            ));
         }
-        let path = std::path::Path::new(path_str);
-        if !path.exists() {
-            return Err(ConfigError::ConfigNotFound(path_str.to_string()));
+    };
+
+    files.including.push(canonical);
+    let mut merged = Value::Object(Map::new());
+    for include in includes {
+        let Value::String(include) = include else {
+            return Err(ConfigError::InvalidConfig(format!(
+                "`include` must be a list of paths, found {include}"
+            )));
+        };
+        // relative to the including file
+        let include_path = path.parent().unwrap_or(Path::new("")).join(include);
+        let include_str = include_path.to_string_lossy();
+        let included = read_document(&include_str, ConfigFormat::from_path(&include_str)?, files)?;
+        merge(&mut merged, included);
+    }
+    files.including.pop();
+    merge(&mut merged, document);
+    Ok(merged)
+}
+
+// lists are concatenated, tables merged and any other value replaced, except
+// that a node table with the id of a merged one updates its fields
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match (base.get_mut(&key), value) {
+                    (Some(Value::Array(nodes)), Value::Array(overlay))
+                        if ["drone", "client", "server"].contains(&key.as_str()) =>
+                    {
+                        for node in overlay {
+                            merge_node(nodes, node);
+                        }
+                    }
+                    (Some(existing), value) => merge(existing, value),
+                    (None, value) => {
+                        base.insert(key, value);
+                    }
+                }
+            }
         }
+        (Value::Array(base), Value::Array(overlay)) => base.extend(overlay),
+        (base, overlay) => *base = overlay,
+    }
+}
 
-        let content = std::fs::read_to_string(path_str)
-            .map_err(|e| ConfigError::ParseError(e.to_string()))?;
+fn merge_node(nodes: &mut Vec<Value>, node: Value) {
+    let existing = node.get("id").and_then(|id| {
+        nodes
+            .iter_mut()
+            .find(|n| n.get("id") == Some(id))
+            .and_then(Value::as_object_mut)
+    });
+    match (existing, node) {
+        (Some(existing), Value::Object(fields)) => existing.extend(fields),
+        (_, node) => nodes.push(node),
+    }
+}
 
-        let config: Config =
-            toml::from_str(&content).map_err(|e| ConfigError::ParseError(e.to_string()))?;
+/// Reads a config file into a plain document: includes are merged, the
+/// `[defaults]` are applied to the drones and the `ids` ranges are expanded.
+/// Drones still missing a value after their own file's defaults take the
+/// merged ones, where the including file wins
+fn resolve_config_file(path_str: &str, format: ConfigFormat) -> Result<Value, ConfigError> {
+    let mut document = read_document(path_str, format, &mut Includes::default())?;
+    let Some(table) = document.as_object_mut() else {
+        return Ok(document);
+    };
+    let defaults = table.remove("defaults").unwrap_or_default();
+    expand_entries(table, &defaults)?;
+    Ok(document)
+}
+
+fn expand_entries(table: &mut Map<String, Value>, defaults: &Value) -> Result<(), ConfigError> {
+    for kind in ["drone", "client", "server"] {
+        if let Some(Value::Array(entries)) = table.get_mut(kind) {
+            let mut expanded = Vec::new();
+            for entry in entries.drain(..) {
+                expanded.extend(expand_entry(entry, kind, defaults)?);
+            }
+            *entries = expanded;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a config file as `T`. A file without includes, defaults or ranges is
+/// read again by its own format on error, whose message has the line and
+/// column that the merged document lost
+fn parse_document<T: DeserializeOwned>(
+    path_str: &str,
+    format: ConfigFormat,
+) -> Result<T, ConfigError> {
+    let document = resolve_config_file(path_str, format)?;
+    serde_json::from_value(document.clone()).map_err(|e| {
+        read_config_file(path_str)
+            .ok()
+            .filter(|content| format.deserialize::<Value>(content).ok() == Some(document))
+            .and_then(|content| format.deserialize::<T>(&content).err())
+            .unwrap_or_else(|| ConfigError::ParseError(e.to_string()))
+    })
+}
+
+// one node table, or one per id when it has `ids = "a..b"`
+fn expand_entry(mut entry: Value, kind: &str, defaults: &Value) -> Result<Vec<Value>, ConfigError> {
+    let Some(table) = entry.as_object_mut() else {
+        return Ok(vec![entry]);
+    };
+    for key in ["connected_node_ids", "connected_drone_ids"] {
+        if let Some(Value::Array(ids)) = table.get_mut(key) {
+            let mut expanded = Vec::new();
+            for id in ids.drain(..) {
+                match id {
+                    Value::String(range) => {
+                        expanded.extend(parse_id_range(&range)?.into_iter().map(Value::from));
+                    }
+                    id => expanded.push(id),
+                }
+            }
+            *ids = expanded;
+        }
+    }
+    if kind == "drone" {
+        for key in ["pdr", "implementation"] {
+            if let Some(value) = defaults.get(key)
+                && !table.contains_key(key)
+            {
+                table.insert(key.to_string(), value.clone());
+            }
+        }
+    }
+
+    let Some(ids) = table.remove("ids") else {
+        return Ok(vec![entry]);
+    };
+    if table.contains_key("id") {
+        return Err(ConfigError::InvalidConfig(format!(
+            "A {kind} cannot have both `id` and `ids`"
+        )));
+    }
+    let Value::String(range) = ids else {
+        return Err(ConfigError::InvalidConfig(format!(
+            "`ids` must be a range such as \"10..20\", found {ids}"
+        )));
+    };
+    Ok(parse_id_range(&range)?
+        .into_iter()
+        .map(|id| {
+            let mut entry = entry.clone();
+            if let Some(table) = entry.as_object_mut() {
+                table.insert("id".to_string(), Value::from(id));
+            }
+            entry
+        })
+        .collect())
+}
+
+// "10..20" excludes 20, "10..=20" includes it
+fn parse_id_range(range: &str) -> Result<Vec<NodeId>, ConfigError> {
+    let invalid = || ConfigError::InvalidConfig(format!("Invalid id range: {range}"));
+    let (start, end) = range.split_once("..").ok_or_else(invalid)?;
+    let (end, inclusive) = match end.strip_prefix('=') {
+        Some(end) => (end, true),
+        None => (end, false),
+    };
+    let start: NodeId = start.trim().parse().map_err(|_| invalid())?;
+    let end: NodeId = end.trim().parse().map_err(|_| invalid())?;
+    let ids: Vec<NodeId> = if inclusive {
+        (start..=end).collect()
+    } else {
+        (start..end).collect()
+    };
+    if ids.is_empty() {
+        return Err(invalid());
+    }
+    Ok(ids)
+}
+
+impl Parse for Config {
+    fn parse_config_as(path_str: &str, format: ConfigFormat) -> Result<Config, ConfigError> {
+        parse_document(path_str, format)
+    }
+}
+
+impl Parse for SimulationSettings {
+    fn parse_config_as(
+        path_str: &str,
+        format: ConfigFormat,
+    ) -> Result<SimulationSettings, ConfigError> {
+        parse_document(path_str, format)
+    }
+}
 
-        Ok(config)
+/// Every problem of the config file at `path`, not only the first one: the
+/// parse error, or the errors of [`Validate::config_errors`] and
+/// [`ValidateSettings::settings_errors`]
+#[must_use]
+pub fn diagnostics(path: &str) -> Vec<ConfigError> {
+    let config = match Config::parse_config(path) {
+        Ok(config) => config,
+        Err(e) => return vec![e],
+    };
+    let mut errors = config.config_errors();
+    match SimulationSettings::parse_config(path) {
+        Ok(settings) => errors.extend(settings.settings_errors(&config)),
+        Err(e) => errors.push(e),
     }
+    errors
 }
 
 pub trait Validate {
-    fn validate_config(&self) -> Result<(), ConfigError>;
+    // the first of `config_errors`
+    fn validate_config(&self) -> Result<(), ConfigError> {
+        self.config_errors().into_iter().next().map_or(Ok(()), Err)
+    }
+    fn config_errors(&self) -> Vec<ConfigError>;
     fn validate_unique_ids(&self) -> Result<(), ConfigError>;
     fn validate_pdr_values(&self) -> Result<(), ConfigError>;
     fn validate_self_connections(&self) -> Result<(), ConfigError>;
@@ -41,14 +308,18 @@ pub trait Validate {
 }
 
 impl Validate for Config {
-    fn validate_config(&self) -> Result<(), ConfigError> {
-        self.validate_unique_ids()?;
-        self.validate_pdr_values()?;
-        self.validate_self_connections()?;
-        self.validate_client_connections()?;
-        self.validate_server_connections()?;
-        self.validate_bidirectional_connections()?;
-        Ok(())
+    fn config_errors(&self) -> Vec<ConfigError> {
+        [
+            self.validate_unique_ids(),
+            self.validate_pdr_values(),
+            self.validate_self_connections(),
+            self.validate_client_connections(),
+            self.validate_server_connections(),
+            self.validate_bidirectional_connections(),
+        ]
+        .into_iter()
+        .filter_map(Result::err)
+        .collect()
     }
 
     fn validate_unique_ids(&self) -> Result<(), ConfigError> {
@@ -206,10 +477,10 @@ impl Validate for Config {
 
         for node in node_map.values() {
             for connected_id in node.connected_node_ids() {
-                if let Some(connected_node) = node_map.get(connected_id) {
-                    if !connected_node.connected_node_ids().contains(&node.id()) {
-                        return Err(ConfigError::UnidirectedConnection);
-                    }
+                if let Some(connected_node) = node_map.get(connected_id)
+                    && !connected_node.connected_node_ids().contains(&node.id())
+                {
+                    return Err(ConfigError::UnidirectedConnection);
                 }
             }
         }
@@ -217,3 +488,203 @@ impl Validate for Config {
         Ok(())
     }
 }
+
+pub trait ValidateSettings {
+    // the first of `settings_errors`
+    fn validate_settings(&self, config: &Config) -> Result<(), ConfigError> {
+        self.settings_errors(config)
+            .into_iter()
+            .next()
+            .map_or(Ok(()), Err)
+    }
+    fn settings_errors(&self, config: &Config) -> Vec<ConfigError>;
+    fn validate_links(&self, config: &Config) -> Result<(), ConfigError>;
+    fn validate_faults(&self, config: &Config) -> Result<(), ConfigError>;
+    fn validate_implementations(&self) -> Result<(), ConfigError>;
+    fn validate_scenario(&self, config: &Config) -> Result<(), ConfigError>;
+}
+
+impl ValidateSettings for SimulationSettings {
+    fn settings_errors(&self, config: &Config) -> Vec<ConfigError> {
+        [
+            self.validate_links(config),
+            self.validate_faults(config),
+            self.validate_implementations(),
+            // a scenario naming another topology is checked against that one
+            match &self.scenario {
+                Some(scenario) if scenario.topology.is_none() => self.validate_scenario(config),
+                _ => Ok(()),
+            },
+        ]
+        .into_iter()
+        .filter_map(Result::err)
+        .collect()
+    }
+
+    fn validate_links(&self, config: &Config) -> Result<(), ConfigError> {
+        let connections: HashSet<(NodeId, NodeId)> = config
+            .drone
+            .iter()
+            .flat_map(|d| d.connected_node_ids.iter().map(move |id| (d.id, *id)))
+            .chain(
+                config
+                    .client
+                    .iter()
+                    .flat_map(|c| c.connected_drone_ids.iter().map(move |id| (c.id, *id))),
+            )
+            .chain(
+                config
+                    .server
+                    .iter()
+                    .flat_map(|s| s.connected_drone_ids.iter().map(move |id| (s.id, *id))),
+            )
+            .collect();
+
+        let mut seen = HashSet::new();
+        for link in &self.link {
+            if !connections.contains(&(link.a, link.b)) {
+                return Err(ConfigError::InvalidLink(format!(
+                    "Link {}-{} does not match any connection in the topology",
+                    link.a, link.b
+                )));
+            }
+            if link.loss.as_ref().is_some_and(|loss| {
+                loss.probabilities()
+                    .iter()
+                    .any(|p| !(0.0..=1.0).contains(p))
+            }) {
+                return Err(ConfigError::InvalidLink(format!(
+                    "Link {}-{} has a loss probability outside [0, 1]",
+                    link.a, link.b
+                )));
+            }
+            if link
+                .latency_ms
+                .checked_add(link.jitter_ms)
+                .is_none_or(|delay| delay > MAX_LINK_DELAY_MS)
+            {
+                return Err(ConfigError::InvalidLink(format!(
+                    "Link {}-{} has a latency plus jitter over {MAX_LINK_DELAY_MS} ms",
+                    link.a, link.b
+                )));
+            }
+            if !seen.insert((link.a.min(link.b), link.a.max(link.b))) {
+                return Err(ConfigError::InvalidLink(format!(
+                    "Link {}-{} is configured more than once",
+                    link.a, link.b
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_faults(&self, config: &Config) -> Result<(), ConfigError> {
+        for fault in &self.fault {
+            validate_fault_action(&fault.action, config)?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_implementations(&self) -> Result<(), ConfigError> {
+        for drone in &self.drone {
+            if let Some(name) = &drone.implementation
+                && drone_implementation_index(name).is_none()
+            {
+                return Err(ConfigError::UnknownImplementation {
+                    drone: drone.id,
+                    name: name.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_scenario(&self, config: &Config) -> Result<(), ConfigError> {
+        let Some(scenario) = &self.scenario else {
+            return Ok(());
+        };
+        let endpoint_types = endpoint_types(config);
+
+        for step in &scenario.steps {
+            match step {
+                ScenarioStep::Command {
+                    node,
+                    command,
+                    path,
+                    ..
+                } => {
+                    let Some(node_type) = endpoint_types.get(node) else {
+                        return Err(ConfigError::InvalidScenario(format!(
+                            "Node {node} is not a client or a server"
+                        )));
+                    };
+                    let command = node_command(command, path.as_deref())
+                        .map_err(ConfigError::InvalidScenario)?;
+                    check_command(*node_type, command.as_ref())
+                        .map_err(|e| ConfigError::InvalidScenario(format!("Node {node}: {e}")))?;
+                }
+                ScenarioStep::Expect { expect, from, .. } => {
+                    if !EVENT_KINDS.contains(&event_kind(expect).as_str()) {
+                        return Err(ConfigError::InvalidScenario(format!(
+                            "Unknown event `{expect}`"
+                        )));
+                    }
+                    if !endpoint_types.contains_key(from)
+                        && !config.drone.iter().any(|d| d.id == *from)
+                    {
+                        return Err(ConfigError::InvalidScenario(format!(
+                            "Node {from} is not in the topology"
+                        )));
+                    }
+                }
+                ScenarioStep::Network { action, .. } => {
+                    validate_fault_action(action, config).map_err(|e| match e {
+                        ConfigError::InvalidFault(msg) => ConfigError::InvalidScenario(msg),
+                        e => e,
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// the nodes of a `[[fault]]` or scenario action exist and can take it
+fn validate_fault_action(action: &FaultAction, config: &Config) -> Result<(), ConfigError> {
+    let drone_ids: HashSet<NodeId> = config.drone.iter().map(|d| d.id).collect();
+    let node_ids: HashSet<NodeId> = drone_ids
+        .iter()
+        .copied()
+        .chain(config.client.iter().map(|c| c.id))
+        .chain(config.server.iter().map(|s| s.id))
+        .collect();
+
+    match *action {
+        FaultAction::Crash { node } | FaultAction::SetPdr { node, .. }
+            if !drone_ids.contains(&node) =>
+        {
+            return Err(ConfigError::InvalidFault(format!(
+                "Node {node} is not a drone"
+            )));
+        }
+        FaultAction::SetPdr { pdr, .. } if !(0.0..=1.0).contains(&pdr) => {
+            return Err(ConfigError::InvalidPdrValue);
+        }
+        FaultAction::AddLink { a, b } | FaultAction::RemoveLink { a, b } => {
+            if let Some(id) = [a, b].into_iter().find(|id| !node_ids.contains(id)) {
+                return Err(ConfigError::InvalidFault(format!(
+                    "Node {id} is not in the topology"
+                )));
+            }
+            if a == b {
+                return Err(ConfigError::InvalidFault(format!(
+                    "Node {a} cannot be linked to itself"
+                )));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,201 @@
+//! Queries over an event stream, so tests wait for the event they care about
+//! instead of asserting on the exact order of everything the nodes emit:
+//!
+//! ```ignore
+//! let received = events.wait_for(
+//!     |e: &NodeEvent| matches!(e, NodeEvent::MessageReceived { .. }),
+//!     Duration::from_secs(5),
+//! )?;
+//! ```
+
+use crate::broadcast::SimulationEvent;
+use crate::errors::EventError;
+use common::types::Event;
+use crossbeam::channel::{Receiver, RecvTimeoutError};
+use std::any::Any;
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+use wg_internal::controller::DroneEvent;
+
+/// An item of an event stream that may hold an event of type `T`
+pub trait Observed: Debug {
+    fn as_event<T: Any>(&self) -> Option<&T>;
+}
+
+impl Observed for Box<dyn Event> {
+    fn as_event<T: Any>(&self) -> Option<&T> {
+        let event: &dyn Any = self.as_ref();
+        event.downcast_ref()
+    }
+}
+
+impl Observed for DroneEvent {
+    fn as_event<T: Any>(&self) -> Option<&T> {
+        let event: &dyn Any = self;
+        event.downcast_ref()
+    }
+}
+
+impl Observed for SimulationEvent {
+    fn as_event<T: Any>(&self) -> Option<&T> {
+        let event: &dyn Any = self;
+        event.downcast_ref().or_else(|| match self {
+            SimulationEvent::Drone(_, event) => (event as &dyn Any).downcast_ref(),
+            SimulationEvent::Node(..) => self.node_event(),
+        })
+    }
+}
+
+pub trait EventQuery {
+    type Item: Observed;
+
+    /// The first event of type `T` matching `predicate`, skipping any other
+    ///
+    /// # Errors
+    /// Returns every event received if none matched within `timeout` or the
+    /// channel closed
+    fn wait_for<T: Any + Clone>(
+        &self,
+        predicate: impl FnMut(&T) -> bool,
+        timeout: Duration,
+    ) -> Result<T, EventError>;
+
+    /// Every event received up to and including the first event of type `T`
+    /// matching `predicate`
+    ///
+    /// # Errors
+    /// Returns every event received if none matched within `timeout` or the
+    /// channel closed
+    fn collect_until<T: Any>(
+        &self,
+        predicate: impl FnMut(&T) -> bool,
+        timeout: Duration,
+    ) -> Result<Vec<Self::Item>, EventError>;
+}
+
+impl<I: Observed> EventQuery for Receiver<I> {
+    type Item = I;
+
+    fn wait_for<T: Any + Clone>(
+        &self,
+        predicate: impl FnMut(&T) -> bool,
+        timeout: Duration,
+    ) -> Result<T, EventError> {
+        let events = self.collect_until(predicate, timeout)?;
+        Ok(events
+            .last()
+            .and_then(Observed::as_event::<T>)
+            .cloned()
+            .expect("The last event matched"))
+    }
+
+    fn collect_until<T: Any>(
+        &self,
+        mut predicate: impl FnMut(&T) -> bool,
+        timeout: Duration,
+    ) -> Result<Vec<I>, EventError> {
+        let deadline = Instant::now() + timeout;
+        let mut events = Vec::new();
+        loop {
+            let event = match self.recv_deadline(deadline) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(EventError::Timeout(timeout, describe(&events)));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(EventError::Disconnected(describe(&events)));
+                }
+            };
+            let matched = event.as_event().is_some_and(&mut predicate);
+            events.push(event);
+            if matched {
+                return Ok(events);
+            }
+        }
+    }
+}
+
+fn describe(events: &[impl Debug]) -> Vec<String> {
+    events.iter().map(|event| format!("{event:?}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Channel;
+    use common::types::{NodeEvent, WebEvent};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_event_queries() {
+        let events: Channel<Box<dyn Event>> = Channel::new();
+        events
+            .send(Box::new(NodeEvent::FloodStarted(1, 1)))
+            .unwrap();
+        events
+            .send(Box::new(WebEvent::FilesLists {
+                notification_from: 1,
+                files_map: HashMap::new(),
+            }))
+            .unwrap();
+        events
+            .send(Box::new(NodeEvent::FloodStarted(2, 1)))
+            .unwrap();
+
+        let flood = events
+            .get_receiver()
+            .wait_for(
+                |e: &NodeEvent| matches!(e, NodeEvent::FloodStarted(2, _)),
+                Duration::from_millis(100),
+            )
+            .unwrap();
+        assert!(matches!(flood, NodeEvent::FloodStarted(2, 1)));
+
+        events
+            .send(Box::new(NodeEvent::FloodStarted(3, 1)))
+            .unwrap();
+        let error = events
+            .get_receiver()
+            .wait_for(
+                |e: &WebEvent| matches!(e, WebEvent::FilesLists { .. }),
+                Duration::from_millis(50),
+            )
+            .unwrap_err();
+        assert!(matches!(&error, EventError::Timeout(_, seen) if seen.len() == 1));
+        assert!(error.to_string().contains("FloodStarted(3, 1)"));
+
+        let subscriber = Channel::new();
+        subscriber
+            .send(SimulationEvent::Node(
+                4,
+                Arc::new(NodeEvent::FloodStarted(5, 4)),
+            ))
+            .unwrap();
+        subscriber
+            .send(SimulationEvent::Node(
+                1,
+                Arc::new(WebEvent::FilesLists {
+                    notification_from: 1,
+                    files_map: HashMap::new(),
+                }),
+            ))
+            .unwrap();
+        let collected = subscriber
+            .get_receiver()
+            .collect_until(
+                |e: &WebEvent| matches!(e, WebEvent::FilesLists { .. }),
+                Duration::from_millis(100),
+            )
+            .unwrap();
+        assert_eq!(collected.len(), 2);
+        assert_eq!(collected[1].source(), 1);
+
+        let receiver = subscriber.get_receiver();
+        drop(subscriber);
+        assert!(matches!(
+            receiver.wait_for(|_: &SimulationEvent| true, Duration::from_secs(1)),
+            Err(EventError::Disconnected(seen)) if seen.is_empty()
+        ));
+    }
+}
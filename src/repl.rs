@@ -0,0 +1,307 @@
+//! Interactive console for a running simulation: every line is a command such
+//! as `crash 3`, `pdr 2 0.4`, `send 1 query-files`, `link add 1 4`,
+//! `events tail` or `topology`.
+
+use crate::broadcast::{EventFilter, SimulationEvent};
+use crate::control::NetworkHandle;
+use crate::network_initializer::{NetworkInitializer, Running};
+use crate::utils::check_command;
+use common::types::{Command, NodeCommand, WebCommand};
+use crossbeam::channel::{Sender, select};
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Instant;
+use wg_internal::network::NodeId;
+
+const HELP: &str = "\
+crash <drone>                 crash a drone
+pdr <drone> <value>           change the packet drop rate of a drone
+send <node> <command>         query-files, list-files, add-file <path> or shutdown
+link add|remove <a> <b>       connect or disconnect two nodes
+events tail [n]               last n events (10 by default)
+topology                      every node with its neighbors
+pause | resume                hold or release packets on every link
+quit                          stop the simulation and exit";
+
+// events kept for `events tail`
+const EVENTS_KEPT: usize = 1000;
+
+/// What the console does after a command
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Output(String),
+    Quit,
+}
+
+pub struct Repl {
+    running: NetworkInitializer<Running>,
+    network: NetworkHandle,
+    events: Arc<Mutex<VecDeque<String>>>,
+    // dropping it stops the event collector
+    stop: Option<Sender<()>>,
+    collector: Option<JoinHandle<()>>,
+}
+
+impl Repl {
+    /// Takes over a running simulation, recording its events for `events tail`
+    #[must_use]
+    pub fn new(running: NetworkInitializer<Running>) -> Self {
+        let events = Arc::new(Mutex::new(VecDeque::new()));
+        let (stop, stopped) = crossbeam::channel::bounded::<()>(0);
+        let received = running.subscribe(EventFilter::all());
+        let collector = {
+            let events = events.clone();
+            let start = Instant::now();
+            std::thread::spawn(move || {
+                loop {
+                    let line = select! {
+                        recv(received) -> event => match event {
+                            Ok(SimulationEvent::Drone(id, event)) => format!("drone {id} {event:?}"),
+                            Ok(SimulationEvent::Node(id, event)) => format!("node {id} {event:?}"),
+                            Err(_) => return,
+                        },
+                        recv(stopped) -> _ => return,
+                    };
+                    let mut events = events.lock().unwrap_or_else(PoisonError::into_inner);
+                    if events.len() == EVENTS_KEPT {
+                        events.pop_front();
+                    }
+                    events.push_back(format!("[{:>8.3}s] {line}", start.elapsed().as_secs_f64()));
+                }
+            })
+        };
+        Self {
+            network: running.get_network_handle(),
+            running,
+            events,
+            stop: Some(stop),
+            collector: Some(collector),
+        }
+    }
+
+    /// Executes one line
+    ///
+    /// # Errors
+    /// Returns a message if the command is unknown or cannot be applied
+    pub fn execute(&mut self, line: &str) -> Result<Reply, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let output = match words.as_slice() {
+            [] => String::new(),
+            ["help"] => HELP.to_string(),
+            ["quit" | "exit"] => return Ok(Reply::Quit),
+            ["crash", id] => {
+                let id = parse(id)?;
+                self.network.crash_drone(id).map_err(|e| e.to_string())?;
+                format!("Drone {id} crashed")
+            }
+            ["pdr", id, pdr] => {
+                let id = parse(id)?;
+                let pdr = parse(pdr)?;
+                self.network.set_pdr(id, pdr).map_err(|e| e.to_string())?;
+                format!("Drone {id} now drops {pdr} of the packets")
+            }
+            ["link", "add", a, b] => {
+                let (a, b) = (parse(a)?, parse(b)?);
+                self.network.add_link(a, b).map_err(|e| e.to_string())?;
+                format!("Linked {a} and {b}")
+            }
+            ["link", "remove", a, b] => {
+                let (a, b) = (parse(a)?, parse(b)?);
+                self.network.remove_link(a, b).map_err(|e| e.to_string())?;
+                format!("Unlinked {a} and {b}")
+            }
+            ["send", id, command @ ..] => {
+                let id = parse(id)?;
+                self.send(id, command)?;
+                format!("Sent {} to {id}", command.join(" "))
+            }
+            ["events", "tail"] => self.tail(10),
+            ["events", "tail", n] => self.tail(parse(n)?),
+            ["topology"] => self.topology(),
+            ["pause"] => {
+                self.running.pause();
+                "Links paused".to_string()
+            }
+            ["resume"] => {
+                self.running.resume();
+                "Links resumed".to_string()
+            }
+            _ => return Err(format!("Unknown command: {line}, type `help`")),
+        };
+        Ok(Reply::Output(output))
+    }
+
+    fn send(&self, id: NodeId, command: &[&str]) -> Result<(), String> {
+        // looked up on every command, a restarted node has new channels
+        let (node_type, sender) = self
+            .running
+            .get_clients()
+            .remove(&id)
+            .or_else(|| self.running.get_servers().remove(&id))
+            .ok_or_else(|| format!("Node {id} is not a client or a server"))?;
+        let command: Box<dyn Command> = match command {
+            ["query-files"] => Box::new(WebCommand::QueryTextFilesList),
+            ["list-files"] => Box::new(WebCommand::GetTextFilesList),
+            ["add-file", path] => Box::new(WebCommand::AddTextFileFromPath((*path).to_string())),
+            ["shutdown"] => Box::new(NodeCommand::Shutdown),
+            _ => return Err(format!("Unknown node command: {}", command.join(" "))),
+        };
+        check_command(node_type, command.as_ref()).map_err(|e| format!("Node {id}: {e}"))?;
+        sender
+            .send(command)
+            .map_err(|_| format!("Node {id} is not running"))
+    }
+
+    fn tail(&self, n: usize) -> String {
+        let events = self.events.lock().unwrap_or_else(PoisonError::into_inner);
+        let skip = events.len().saturating_sub(n);
+        events
+            .iter()
+            .skip(skip)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn topology(&self) -> String {
+        let topology = self.network.read_topology();
+        let mut out = String::new();
+        for d in &topology.drone {
+            let _ = writeln!(
+                out,
+                "drone {} (pdr {}): {:?}",
+                d.id, d.pdr, d.connected_node_ids
+            );
+        }
+        for c in &topology.client {
+            let _ = writeln!(out, "client {}: {:?}", c.id, c.connected_drone_ids);
+        }
+        for s in &topology.server {
+            let _ = writeln!(out, "server {}: {:?}", s.id, s.connected_drone_ids);
+        }
+        out.trim_end().to_string()
+    }
+
+    /// Reads commands from `input` until `quit` or the end of the input, then
+    /// stops the simulation, also when `input` or `out` fail
+    ///
+    /// # Errors
+    /// Returns an error if `input` or `out` fail
+    pub fn run(mut self, input: impl BufRead, out: &mut impl Write) -> std::io::Result<()> {
+        write!(out, "> ")?;
+        out.flush()?;
+        for line in input.lines() {
+            match self.execute(&line?) {
+                Ok(Reply::Quit) => break,
+                Ok(Reply::Output(output)) if output.is_empty() => {}
+                Ok(Reply::Output(output)) => writeln!(out, "{output}")?,
+                Err(message) => writeln!(out, "error: {message}")?,
+            }
+            write!(out, "> ")?;
+            out.flush()?;
+        }
+        self.stop();
+        Ok(())
+    }
+
+    /// Stops the event collector and the simulation, once
+    pub fn stop(&mut self) {
+        if self.stop.take().is_none() {
+            return;
+        }
+        if let Some(collector) = self.collector.take() {
+            let _ = collector.join();
+        }
+        self.running.stop_simulation();
+    }
+}
+
+// a console left by an error or a panic still stops the simulation
+impl Drop for Repl {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid number: {value}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ControlError;
+    use crate::network_initializer::Uninitialized;
+
+    #[test]
+    fn test_repl_commands() {
+        let running = NetworkInitializer::<Uninitialized>::new("./tests/correct_config.toml")
+            .initialize()
+            .start_simulation();
+        let mut repl = Repl::new(running);
+        let output = |repl: &mut Repl, line| match repl.execute(line) {
+            Ok(Reply::Output(output)) => output,
+            other => panic!("Unexpected reply to {line}: {other:?}"),
+        };
+
+        output(&mut repl, "pdr 2 0.4");
+        output(&mut repl, "link add 1 4");
+        output(&mut repl, "crash 3");
+        let topology = output(&mut repl, "topology");
+        assert!(topology.contains("drone 2 (pdr 0.4): [1, 4, 6]"));
+        assert!(topology.contains("client 4: [2, 1]"));
+        assert!(!topology.contains("drone 3"));
+        output(&mut repl, "send 4 query-files");
+        output(&mut repl, "events tail 5");
+
+        assert!(repl.execute("crash 4").is_err());
+        assert!(repl.execute("pdr 1 2").is_err());
+        assert!(repl.execute("send 1 query-files").is_err());
+        assert!(repl.execute("send 4 fly").is_err());
+        assert!(
+            repl.execute("send 5 query-files").is_err(),
+            "Client 5 is a chat client"
+        );
+        assert!(repl.execute("send 4 add-file ./tests/test.txt").is_err());
+        output(&mut repl, "send 6 add-file ./tests/test.txt");
+        assert!(repl.execute("warp 9").is_err());
+
+        let mut out = Vec::new();
+        repl.run("help\nquit\n".as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("events tail [n]"));
+    }
+
+    struct BrokenPipe;
+
+    impl Write for BrokenPipe {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_repl_stops_on_error() {
+        let running = NetworkInitializer::<Uninitialized>::new("./tests/correct_config.toml")
+            .initialize()
+            .start_simulation();
+        let network = running.get_network_handle();
+        let repl = Repl::new(running);
+
+        assert!(repl.run("topology\n".as_bytes(), &mut BrokenPipe).is_err());
+        assert_eq!(
+            network.add_link(5, 3),
+            Err(ControlError::UnknownNode(3)),
+            "The simulation is stopped"
+        );
+    }
+}
@@ -0,0 +1,656 @@
+//! Scenario tests: a topology, timed actions (commands to clients and servers,
+//! drone crashes, link and PDR changes) and the events expected in return,
+//! e.g. "client 1 receives `FilesLists` within 5s". [`Scenario::run`] starts
+//! the network, drives it, checks the expectations against the event stream,
+//! stops it and returns a [`ScenarioReport`].
+//!
+//! Scenarios can also be written in a `[scenario]` section, in the topology
+//! file itself or in a separate file that names it:
+//!
+//! ```toml
+//! [scenario]
+//! topology = "../config/simple_config.toml"
+//! steps = [
+//!     { at = "1s", node = 1, command = "query_text_files_list" },
+//!     { expect = "files_lists", from = 1, within = "3s" },
+//!     { at = "2s", action = "crash", node = 3 },
+//! ]
+//! ```
+//!
+//! An expectation's window starts at the last action listed before it.
+
+use crate::broadcast::{EventFilter, SimulationEvent};
+use crate::errors::ConfigError;
+use crate::fault::FaultAction;
+use crate::format::read_config;
+use crate::network_initializer::{NetworkInitializer, Uninitialized};
+use crate::parser::{Parse, ValidateSettings, diagnostics};
+use crate::settings::SimulationSettings;
+use crate::utils::{check_command, deserialize_duration};
+use common::types::{Command, NodeCommand, WebCommand};
+use crossbeam::channel::{RecvTimeoutError, never};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use wg_internal::network::NodeId;
+
+/// The `[scenario]` section of a file
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScenarioSettings {
+    // path of the topology, relative to this file, if it is not this file
+    #[serde(default)]
+    pub topology: Option<String>,
+    #[serde(default)]
+    pub steps: Vec<ScenarioStep>,
+}
+
+/// A step of a scenario, told apart by its `expect`, `command` or `action`
+/// key so that a misspelled field is reported for that kind of step
+#[derive(Debug, Clone)]
+pub enum ScenarioStep {
+    // an event kind in snake case, e.g. "files_lists"
+    Expect {
+        expect: String,
+        from: NodeId,
+        within: Duration,
+    },
+    // see `node_command` for the command names
+    Command {
+        at: Duration,
+        node: NodeId,
+        command: String,
+        path: Option<String>,
+    },
+    // the actions of `[[fault]]`
+    Network {
+        at: Duration,
+        action: FaultAction,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExpectStep {
+    expect: String,
+    from: NodeId,
+    #[serde(deserialize_with = "deserialize_duration")]
+    within: Duration,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CommandStep {
+    #[serde(deserialize_with = "deserialize_duration")]
+    at: Duration,
+    node: NodeId,
+    command: String,
+    #[serde(default)]
+    path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NetworkStep {
+    #[serde(deserialize_with = "deserialize_duration")]
+    at: Duration,
+    #[serde(flatten)]
+    action: FaultAction,
+}
+
+impl<'de> Deserialize<'de> for ScenarioStep {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let step = Map::<String, Value>::deserialize(deserializer)?;
+        let Some(kind) = ["expect", "command", "action"]
+            .into_iter()
+            .find(|key| step.contains_key(*key))
+        else {
+            return Err(D::Error::custom(
+                "a scenario step needs an `expect`, `command` or `action` key",
+            ));
+        };
+        let step = Value::Object(step);
+        let invalid =
+            |e: serde_json::Error| D::Error::custom(format!("invalid `{kind}` step: {e}"));
+        match kind {
+            "expect" => {
+                let ExpectStep {
+                    expect,
+                    from,
+                    within,
+                } = serde_json::from_value(step).map_err(invalid)?;
+                Ok(ScenarioStep::Expect {
+                    expect,
+                    from,
+                    within,
+                })
+            }
+            "command" => {
+                let CommandStep {
+                    at,
+                    node,
+                    command,
+                    path,
+                } = serde_json::from_value(step).map_err(invalid)?;
+                Ok(ScenarioStep::Command {
+                    at,
+                    node,
+                    command,
+                    path,
+                })
+            }
+            _ => {
+                let NetworkStep { at, action } = serde_json::from_value(step).map_err(invalid)?;
+                Ok(ScenarioStep::Network { at, action })
+            }
+        }
+    }
+}
+
+/// The command named `name` in a scenario file; `path` is the file to add for
+/// `add_text_file_from_path`
+pub(crate) fn node_command(name: &str, path: Option<&str>) -> Result<Box<dyn Command>, String> {
+    match (name, path) {
+        ("query_text_files_list", _) => Ok(Box::new(WebCommand::QueryTextFilesList)),
+        ("get_text_files_list", _) => Ok(Box::new(WebCommand::GetTextFilesList)),
+        ("add_text_file_from_path", Some(path)) => {
+            Ok(Box::new(WebCommand::AddTextFileFromPath(path.to_string())))
+        }
+        ("add_text_file_from_path", None) => {
+            Err("add_text_file_from_path needs a path".to_string())
+        }
+        ("shutdown", _) => Ok(Box::new(NodeCommand::Shutdown)),
+        (other, _) => Err(format!("Unknown command: {other}")),
+    }
+}
+
+// "files_lists" -> "FilesLists", the name of the event variant
+pub(crate) fn event_kind(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// What a scenario does to the network at a given time
+#[derive(Debug)]
+pub enum Action {
+    // a `WebCommand`, `NodeCommand`, ... for a client or a server
+    Command(NodeId, Box<dyn Command>),
+    Crash(NodeId),
+    SetPdr(NodeId, f32),
+    AddLink(NodeId, NodeId),
+    RemoveLink(NodeId, NodeId),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Command(node, command) => write!(f, "send {command:?} to {node}"),
+            Action::Crash(node) => write!(f, "crash {node}"),
+            Action::SetPdr(node, pdr) => write!(f, "set the pdr of {node} to {pdr}"),
+            Action::AddLink(a, b) => write!(f, "link {a} and {b}"),
+            Action::RemoveLink(a, b) => write!(f, "unlink {a} and {b}"),
+        }
+    }
+}
+
+/// An event that must be emitted in the window from `after` to
+/// `after + within` since the start of the scenario
+pub struct Expectation {
+    description: String,
+    after: Duration,
+    within: Duration,
+    matcher: Box<dyn Fn(&SimulationEvent) -> bool>,
+}
+
+impl Expectation {
+    /// An event of `kind` (see [`SimulationEvent::kind`]) emitted by `from`
+    #[must_use]
+    pub fn event(from: NodeId, kind: &str, within: Duration) -> Self {
+        let filter = EventFilter::all().with_nodes([from]).with_kinds([kind]);
+        Self {
+            description: format!("{kind} from {from} within {within:?}"),
+            after: Duration::ZERO,
+            within,
+            matcher: Box::new(move |event| filter.matches(event)),
+        }
+    }
+
+    /// Any event for which `matcher` holds
+    #[must_use]
+    pub fn matching(
+        description: &str,
+        within: Duration,
+        matcher: impl Fn(&SimulationEvent) -> bool + 'static,
+    ) -> Self {
+        Self {
+            description: format!("{description} within {within:?}"),
+            after: Duration::ZERO,
+            within,
+            matcher: Box::new(matcher),
+        }
+    }
+
+    /// Starts the window later than the start of the scenario, e.g. when the
+    /// action that triggers the event happens
+    #[must_use]
+    pub fn after(mut self, after: Duration) -> Self {
+        self.after = after;
+        self
+    }
+
+    fn deadline(&self) -> Duration {
+        self.after + self.within
+    }
+}
+
+/// Whether an expectation was met, and when
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Met(Duration),
+    Missed,
+}
+
+#[derive(Debug)]
+pub struct ScenarioReport {
+    pub duration: Duration,
+    pub events: usize,
+    // description of every expectation with its outcome
+    pub expectations: Vec<(String, Outcome)>,
+    // actions that could not be applied
+    pub errors: Vec<String>,
+}
+
+impl ScenarioReport {
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.errors.is_empty()
+            && self
+                .expectations
+                .iter()
+                .all(|(_, outcome)| matches!(outcome, Outcome::Met(_)))
+    }
+}
+
+impl fmt::Display for ScenarioReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} after {:?} and {} events",
+            if self.passed() { "PASSED" } else { "FAILED" },
+            self.duration,
+            self.events
+        )?;
+        for (description, outcome) in &self.expectations {
+            match outcome {
+                Outcome::Met(at) => writeln!(f, "  ok      {description} (at {at:?})")?,
+                Outcome::Missed => writeln!(f, "  missed  {description}")?,
+            }
+        }
+        for error in &self.errors {
+            writeln!(f, "  error   {error}")?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Scenario {
+    topology: String,
+    actions: Vec<(Duration, Action)>,
+    expectations: Vec<Expectation>,
+}
+
+impl Scenario {
+    /// A scenario over the network of the config at `topology`
+    #[must_use]
+    pub fn new(topology: &str) -> Self {
+        Self {
+            topology: topology.to_string(),
+            actions: Vec::new(),
+            expectations: Vec::new(),
+        }
+    }
+
+    /// The scenario of the `[scenario]` section of the file at `path`
+    ///
+    /// # Errors
+    /// Returns an error if the file has no valid scenario or its topology is
+    /// not a valid config
+    pub fn from_file(path: &str) -> Result<Self, ConfigError> {
+        let settings = SimulationSettings::parse_config(path)?;
+        let scenario = settings
+            .scenario
+            .as_ref()
+            .ok_or_else(|| ConfigError::InvalidScenario(format!("No [scenario] in {path}")))?;
+        let topology = match &scenario.topology {
+            Some(topology) => Path::new(path)
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(topology)
+                .to_string_lossy()
+                .into_owned(),
+            None => path.to_string(),
+        };
+        settings.validate_scenario(&read_config(&topology, None)?)?;
+
+        let mut this = Self::new(&topology);
+        let mut last_action = Duration::ZERO;
+        for step in &scenario.steps {
+            this = match step {
+                ScenarioStep::Expect {
+                    expect,
+                    from,
+                    within,
+                } => this.expect(
+                    Expectation::event(*from, &event_kind(expect), *within).after(last_action),
+                ),
+                ScenarioStep::Command {
+                    at,
+                    node,
+                    command,
+                    path,
+                } => {
+                    last_action = *at;
+                    let command = node_command(command, path.as_deref())
+                        .map_err(ConfigError::InvalidScenario)?;
+                    this.at(*at, Action::Command(*node, command))
+                }
+                ScenarioStep::Network { at, action } => {
+                    last_action = *at;
+                    let action = match *action {
+                        FaultAction::Crash { node } => Action::Crash(node),
+                        FaultAction::SetPdr { node, pdr } => Action::SetPdr(node, pdr),
+                        FaultAction::AddLink { a, b } => Action::AddLink(a, b),
+                        FaultAction::RemoveLink { a, b } => Action::RemoveLink(a, b),
+                    };
+                    this.at(*at, action)
+                }
+            };
+        }
+        Ok(this)
+    }
+
+    #[must_use]
+    pub fn at(mut self, at: Duration, action: Action) -> Self {
+        self.actions.push((at, action));
+        self
+    }
+
+    #[must_use]
+    pub fn expect(mut self, expectation: Expectation) -> Self {
+        self.expectations.push(expectation);
+        self
+    }
+
+    /// Runs the scenario until every action is applied and every expectation
+    /// is met or past its window, then stops the network
+    ///
+    /// # Errors
+    /// Returns the first problem of the topology if it is not a valid config
+    pub fn run(mut self) -> Result<ScenarioReport, ConfigError> {
+        if let Some(error) = diagnostics(&self.topology).into_iter().next() {
+            return Err(error);
+        }
+        let initialized = NetworkInitializer::<Uninitialized>::new(&self.topology).initialize();
+        let mut events = initialized.subscribe(EventFilter::all());
+        let mut running = initialized.start_simulation();
+        let network = running.get_network_handle();
+        let nodes: HashMap<_, _> = running
+            .get_clients()
+            .into_iter()
+            .chain(running.get_servers())
+            .collect();
+
+        self.actions.sort_by_key(|(at, _)| *at);
+        let mut actions = VecDeque::from(self.actions);
+        let mut outcomes = vec![None; self.expectations.len()];
+        let mut report = ScenarioReport {
+            duration: Duration::ZERO,
+            events: 0,
+            expectations: Vec::new(),
+            errors: Vec::new(),
+        };
+        let start = Instant::now();
+        loop {
+            let now = start.elapsed();
+            while actions.front().is_some_and(|(at, _)| *at <= now) {
+                let (at, action) = actions.pop_front().expect("Checked by the loop");
+                let description = action.to_string();
+                let result = match action {
+                    Action::Command(id, command) => match nodes.get(&id) {
+                        Some((node_type, sender)) => check_command(*node_type, command.as_ref())
+                            .and_then(|()| {
+                                sender
+                                    .send(command)
+                                    .map_err(|_| format!("Node {id} is not running"))
+                            }),
+                        None => Err(format!("Node {id} is not a client or a server")),
+                    },
+                    Action::Crash(node) => FaultAction::Crash { node }
+                        .apply(&network)
+                        .map_err(|e| e.to_string()),
+                    Action::SetPdr(node, pdr) => FaultAction::SetPdr { node, pdr }
+                        .apply(&network)
+                        .map_err(|e| e.to_string()),
+                    Action::AddLink(a, b) => FaultAction::AddLink { a, b }
+                        .apply(&network)
+                        .map_err(|e| e.to_string()),
+                    Action::RemoveLink(a, b) => FaultAction::RemoveLink { a, b }
+                        .apply(&network)
+                        .map_err(|e| e.to_string()),
+                };
+                if let Err(e) = result {
+                    report.errors.push(format!("{description} at {at:?}: {e}"));
+                }
+            }
+
+            // the next action, or the end of the earliest open window
+            let open = self
+                .expectations
+                .iter()
+                .zip(&outcomes)
+                .filter(|(e, outcome)| outcome.is_none() && e.deadline() >= now)
+                .map(|(e, _)| e.deadline());
+            let Some(wake) = actions
+                .front()
+                .map(|(at, _)| *at)
+                .into_iter()
+                .chain(open)
+                .min()
+            else {
+                break;
+            };
+            match events.recv_deadline(start + wake) {
+                Ok(event) => {
+                    let at = start.elapsed();
+                    report.events += 1;
+                    for (expectation, outcome) in self.expectations.iter().zip(&mut outcomes) {
+                        if outcome.is_none()
+                            && (expectation.after..=expectation.deadline()).contains(&at)
+                            && (expectation.matcher)(&event)
+                        {
+                            *outcome = Some(at);
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                // every node stopped, nothing else can be met
+                Err(RecvTimeoutError::Disconnected) => events = never(),
+            }
+        }
+        running.stop_simulation();
+
+        report.duration = start.elapsed();
+        report.expectations = self
+            .expectations
+            .into_iter()
+            .zip(outcomes)
+            .map(|(e, outcome)| (e.description, outcome.map_or(Outcome::Missed, Outcome::Met)))
+            .collect();
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::types::WebCommand;
+
+    #[test]
+    fn test_scenario_report() {
+        // 4 is a web browser and 6 a text server
+        let report = Scenario::new("./tests/correct_config.toml")
+            .at(
+                Duration::from_millis(10),
+                Action::Command(4, Box::new(WebCommand::GetTextFilesList)),
+            )
+            .at(
+                Duration::from_millis(10),
+                Action::Command(
+                    6,
+                    Box::new(WebCommand::AddTextFileFromPath(
+                        "./tests/test.txt".to_string(),
+                    )),
+                ),
+            )
+            .at(Duration::from_millis(20), Action::SetPdr(2, 0.5))
+            .expect(
+                Expectation::event(4, "FilesLists", Duration::from_secs(5))
+                    .after(Duration::from_millis(10)),
+            )
+            .expect(
+                Expectation::event(6, "TextFileAdded", Duration::from_secs(5))
+                    .after(Duration::from_millis(10)),
+            )
+            .run()
+            .unwrap();
+
+        assert!(report.passed(), "{report}");
+        assert!(report.expectations.iter().all(
+            |(_, outcome)| matches!(outcome, Outcome::Met(at) if *at >= Duration::from_millis(10))
+        ));
+        assert!(report.to_string().starts_with("PASSED"));
+
+        let report = Scenario::new("./tests/correct_config.toml").run().unwrap();
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn test_scenario_misses() {
+        // 5 is a chat client, it never lists files
+        let report = Scenario::new("./tests/correct_config.toml")
+            .at(Duration::from_millis(10), Action::Crash(4))
+            .expect(Expectation::event(
+                5,
+                "FilesLists",
+                Duration::from_millis(50),
+            ))
+            .run()
+            .unwrap();
+
+        assert!(!report.passed());
+        assert!(report.duration >= Duration::from_millis(50));
+        assert_eq!(
+            report.expectations,
+            vec![("FilesLists from 5 within 50ms".to_string(), Outcome::Missed)]
+        );
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].contains("Node 4 is not a drone"));
+        assert!(report.to_string().starts_with("FAILED"));
+
+        assert!(matches!(
+            Scenario::new("./tests/invalid_config.toml").run(),
+            Err(ConfigError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_scenario_file() {
+        let scenario = Scenario::from_file("./tests/scenario.toml").unwrap();
+        assert_eq!(scenario.topology, "./tests/correct_config.toml");
+        assert_eq!(scenario.actions.len(), 3);
+        assert!(matches!(
+            scenario.actions[2],
+            (at, Action::Crash(1)) if at == Duration::from_millis(150)
+        ));
+
+        let report = scenario.run().unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(matches!(
+            report.expectations.as_slice(),
+            [(description, Outcome::Met(_))] if description == "FilesLists from 4 within 200ms"
+        ));
+        assert!(report.passed());
+
+        assert_eq!(
+            Scenario::from_file("./tests/invalid_scenario.toml").err(),
+            Some(ConfigError::InvalidScenario(
+                "Node 1 is not a client or a server".to_string()
+            ))
+        );
+        assert_eq!(
+            Scenario::from_file("./tests/invalid_expect.toml").err(),
+            Some(ConfigError::InvalidScenario(
+                "Unknown event `file_listed`".to_string()
+            ))
+        );
+        assert!(Scenario::from_file("./tests/correct_config.toml").is_err());
+    }
+
+    #[test]
+    fn test_scenario_step_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let from_steps = |steps: &str| {
+            let path = dir.path().join("scenario.toml");
+            let topology = std::fs::canonicalize("./tests/correct_config.toml").unwrap();
+            std::fs::write(
+                &path,
+                format!("[scenario]\ntopology = {topology:?}\nsteps = [{steps}]\n"),
+            )
+            .unwrap();
+            Scenario::from_file(path.to_str().unwrap()).err()
+        };
+
+        assert_eq!(
+            from_steps(r#"{ at = "1s", node = 5, command = "query_text_files_list" }"#),
+            Some(ConfigError::InvalidScenario(
+                "Node 5: a ChatClient does not handle it, only a WebBrowser".to_string()
+            ))
+        );
+        assert_eq!(
+            from_steps(
+                r#"{ at = "1s", node = 4, command = "add_text_file_from_path", path = "./tests/test.txt" }"#
+            ),
+            Some(ConfigError::InvalidScenario(
+                "Node 4: a WebBrowser does not handle it, only a TextServer".to_string()
+            ))
+        );
+
+        let typo = from_steps(r#"{ expect = "files_lists", form = 4, within = "1s" }"#);
+        assert!(
+            matches!(&typo, Some(ConfigError::ParseError(msg))
+                if msg.contains("invalid `expect` step: unknown field `form`")),
+            "{typo:?}"
+        );
+        let typo = from_steps(r#"{ at = "1s", action = "crash", nod = 1 }"#);
+        assert!(
+            matches!(&typo, Some(ConfigError::ParseError(msg))
+                if msg.contains("invalid `action` step") && msg.contains("`nod`")),
+            "{typo:?}"
+        );
+        let untagged = from_steps(r#"{ at = "1s", node = 4 }"#);
+        assert!(
+            matches!(&untagged, Some(ConfigError::ParseError(msg))
+                if msg.contains("needs an `expect`, `command` or `action` key")),
+            "{untagged:?}"
+        );
+    }
+}
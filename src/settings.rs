@@ -0,0 +1,81 @@
+use crate::engine::ExecutionConfig;
+use crate::fault::Fault;
+use crate::link::LinkConfig;
+use crate::scenario::ScenarioSettings;
+use crate::utils::{
+    client_type, default_drone_implementation, drone_implementation_index,
+    drone_implementation_name, server_type,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use wg_internal::config::Config;
+use wg_internal::network::NodeId;
+
+/// Simulation options read from the same file as the topology, next to the
+/// `[[drone]]`, `[[client]]` and `[[server]]` tables of the `wg_internal` config.
+#[derive(Debug, Default, Deserialize)]
+pub struct SimulationSettings {
+    // makes link loss, fault jitter and node type selection reproducible
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub execution: ExecutionConfig,
+    // per-link impairments, links without an entry deliver instantly
+    #[serde(default)]
+    pub link: Vec<LinkConfig>,
+    // timeline of changes applied while the simulation runs
+    #[serde(default)]
+    pub fault: Vec<Fault>,
+    // the `[[drone]]` tables again, for the fields `wg_internal` does not know
+    #[serde(default)]
+    pub drone: Vec<DroneSettings>,
+    // steps run by `Scenario::from_file`
+    #[serde(default)]
+    pub scenario: Option<ScenarioSettings>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DroneSettings {
+    pub id: NodeId,
+    // name of the drone type to run, e.g. "RustDrone", picked in config order if missing
+    #[serde(default)]
+    pub implementation: Option<String>,
+}
+
+impl SimulationSettings {
+    pub(crate) fn drone_implementation(&self, id: NodeId) -> Option<&str> {
+        self.drone
+            .iter()
+            .find(|d| d.id == id)
+            .and_then(|d| d.implementation.as_deref())
+    }
+
+    /// Index of the implementation run by `drone`, the `i`-th `[[drone]]` of
+    /// the config: the one it names, or the enabled ones in turn. `None` if
+    /// it names an unknown one
+    pub(crate) fn resolve_drone_implementation(&self, i: usize, drone: NodeId) -> Option<usize> {
+        match self.drone_implementation(drone) {
+            Some(name) => drone_implementation_index(name),
+            None => Some(default_drone_implementation(i)),
+        }
+    }
+
+    /// Name of the type every node of `config` runs, as `initialize` picks it
+    pub(crate) fn node_implementations(&self, config: &Config) -> HashMap<NodeId, String> {
+        let drones = config.drone.iter().enumerate().filter_map(|(i, d)| {
+            self.resolve_drone_implementation(i, d.id)
+                .map(|index| (d.id, drone_implementation_name(index).to_string()))
+        });
+        let clients = config
+            .client
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (c.id, format!("{:?}", client_type(i))));
+        let servers = config
+            .server
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.id, format!("{:?}", server_type(i))));
+        drones.chain(clients).chain(servers).collect()
+    }
+}
@@ -0,0 +1,95 @@
+//! Optional routing of `DroneEvent::ControllerShortcut`: a drone that cannot
+//! forward an `Ack`, `Nack` or `FloodResponse` hands it to the controller, which
+//! delivers it straight to the last hop of its routing header. The packet still
+//! travels on a link from the drone, so it is paused, delayed or lost like the
+//! others, and queued by the engine in stepped and manual mode.
+
+use common::types::Event;
+use crossbeam::channel::Sender;
+use std::any::Any;
+use wg_internal::network::NodeId;
+use wg_internal::packet::Packet;
+
+/// Reported as a node event of the drone for every shortcut it hands over
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShortcutEvent {
+    Delivered { to: NodeId, session_id: u64 },
+    // the routing header is empty or its last hop is not a node of the network
+    Undeliverable { to: Option<NodeId>, session_id: u64 },
+}
+
+impl Event for ShortcutEvent {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Sends `packet` to the last hop of its routing header, through the sender
+/// returned by `sender_to`
+pub(crate) fn deliver(
+    mut packet: Packet,
+    sender_to: impl FnOnce(NodeId) -> Option<Sender<Packet>>,
+) -> ShortcutEvent {
+    let session_id = packet.session_id;
+    let to = packet.routing_header.hops.last().copied();
+    let Some(sender) = to.and_then(sender_to) else {
+        return ShortcutEvent::Undeliverable { to, session_id };
+    };
+    // the destination expects to be the current hop
+    packet.routing_header.hop_index = packet.routing_header.hops.len() - 1;
+    match (to, sender.send(packet)) {
+        (Some(to), Ok(())) => ShortcutEvent::Delivered { to, session_id },
+        (to, _) => ShortcutEvent::Undeliverable { to, session_id },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Channel;
+    use std::collections::HashMap;
+    use wg_internal::network::SourceRoutingHeader;
+    use wg_internal::packet::{Ack, PacketType};
+
+    fn ack(hops: Vec<NodeId>, session_id: u64) -> Packet {
+        Packet {
+            routing_header: SourceRoutingHeader::new(hops, 1),
+            session_id,
+            pack_type: PacketType::Ack(Ack { fragment_index: 0 }),
+        }
+    }
+
+    #[test]
+    fn test_shortcut_delivery() {
+        let destination = Channel::new();
+        let senders = HashMap::from([(4, destination.get_sender())]);
+        let sender_to = |to| senders.get(&to).cloned();
+
+        assert_eq!(
+            deliver(ack(vec![6, 2, 3, 4], 7), sender_to),
+            ShortcutEvent::Delivered {
+                to: 4,
+                session_id: 7
+            }
+        );
+        assert_eq!(
+            deliver(ack(vec![6, 2, 9], 8), sender_to),
+            ShortcutEvent::Undeliverable {
+                to: Some(9),
+                session_id: 8
+            }
+        );
+        assert_eq!(
+            deliver(ack(vec![], 9), sender_to),
+            ShortcutEvent::Undeliverable {
+                to: None,
+                session_id: 9
+            }
+        );
+
+        let delivered = destination.get_receiver().recv().unwrap();
+        assert_eq!(delivered.session_id, 7);
+        assert_eq!(delivered.routing_header.hop_index, 3);
+        assert!(destination.get_receiver().try_recv().is_err());
+    }
+}
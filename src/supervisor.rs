@@ -0,0 +1,508 @@
+//! Isolation of the node threads: a drone, client or server that panics in
+//! `run()` is reported right away as a [`NodeCrashed`] node event instead of
+//! only when the simulation stops, and can be removed from the network like a
+//! crashed drone.
+//!
+//! With a [`RestartPolicy`], a supervisor thread starts the nodes whose thread
+//! stopped again, with the same ID and neighbors and new channels, and reports
+//! it as a [`NodeRestarted`] node event, or a [`NodeRestartFailed`] one.
+
+use crate::control::NetworkHandle;
+use crate::errors::ControlError;
+use crate::link::Links;
+use crate::utils::{self, Channel, generate_drone, generate_node, panic_message};
+use common::types::{Event, NodeType as CommonNodeType};
+use crossbeam::channel::{Receiver, Sender, select};
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Instant;
+use wg_internal::config::Config;
+use wg_internal::controller::DroneEvent;
+use wg_internal::network::NodeId;
+use wg_internal::packet::NodeType;
+
+/// Which nodes the supervisor starts again once their thread stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    /// Only the nodes that panicked
+    OnPanic,
+    /// Also the drones that stopped after a crash and the nodes that returned
+    Always,
+}
+
+/// A node thread panicked
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeCrashed {
+    pub id: NodeId,
+    pub kind: NodeType,
+    pub message: String,
+}
+
+impl Event for NodeCrashed {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// A node was started again by the supervisor, `restarts` times so far
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeRestarted {
+    pub id: NodeId,
+    pub kind: NodeType,
+    pub restarts: usize,
+}
+
+impl Event for NodeRestarted {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// The supervisor could not start a node again, e.g. none of its neighbors is
+/// left in the network
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeRestartFailed {
+    pub id: NodeId,
+    pub error: ControlError,
+}
+
+impl Event for NodeRestartFailed {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// How a node thread ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Exit {
+    Finished,
+    Panicked,
+}
+
+/// Health of the thread running a node, see
+/// [`crate::network_initializer::NetworkInitializer::node_status`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeStatus {
+    pub kind: NodeType,
+    /// Drone implementation, or type of client or server
+    pub implementation: String,
+    /// The thread returned or panicked
+    pub finished: bool,
+    pub panicked: bool,
+    /// `None` if the node never emitted an event
+    pub last_event: Option<Instant>,
+}
+
+/// What is known of a node after its thread was joined
+#[derive(Debug, Clone)]
+pub(crate) struct NodeRecord {
+    pub(crate) kind: NodeType,
+    pub(crate) implementation: String,
+    // set when `stop_simulation` joins the thread
+    pub(crate) panicked: bool,
+}
+
+/// The thread running a node
+pub(crate) struct NodeThread {
+    pub(crate) handle: JoinHandle<()>,
+    panicked: Arc<AtomicBool>,
+}
+
+impl NodeThread {
+    pub(crate) fn panicked(&self) -> bool {
+        self.panicked.load(Ordering::SeqCst)
+    }
+
+    /// Waits for the thread: a panic of the node is caught, so the join only
+    /// fails for a panic outside of it
+    pub(crate) fn join(self) -> std::thread::Result<Exit> {
+        self.handle.join()?;
+        Ok(if self.panicked.load(Ordering::SeqCst) {
+            Exit::Panicked
+        } else {
+            Exit::Finished
+        })
+    }
+}
+
+/// Thread running each node, replaced when the node is restarted
+pub(crate) type NodeHandles = Arc<Mutex<HashMap<NodeId, NodeThread>>>;
+
+pub(crate) fn lock_handles(handles: &NodeHandles) -> MutexGuard<'_, HashMap<NodeId, NodeThread>> {
+    handles.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Runs a node on its own thread. If it panics, the node is removed from the
+/// network through `network` when given, then reported on `events`. Either
+/// way its end is sent on `exits`
+pub(crate) fn spawn_isolated(
+    id: NodeId,
+    kind: NodeType,
+    run: impl FnOnce() + Send + 'static,
+    events: Sender<Box<dyn Event>>,
+    network: Option<NetworkHandle>,
+    exits: Sender<(NodeId, Exit)>,
+) -> NodeThread {
+    let panicked = Arc::new(AtomicBool::new(false));
+    let flag = panicked.clone();
+    let handle = std::thread::spawn(move || {
+        let exit = match catch_unwind(AssertUnwindSafe(run)) {
+            Ok(()) => Exit::Finished,
+            Err(panic) => {
+                flag.store(true, Ordering::SeqCst);
+                if let Some(network) = network {
+                    let _ = network.remove_node(id);
+                }
+                let _ = events.send(Box::new(NodeCrashed {
+                    id,
+                    kind,
+                    message: panic_message(panic.as_ref()),
+                }));
+                Exit::Panicked
+            }
+        };
+        let _ = exits.send((id, exit));
+    });
+    NodeThread { handle, panicked }
+}
+
+/// What a node is started again with, besides the new command and packet
+/// channels it gets on every restart: its type and the senders of its events
+pub(crate) enum Recipe {
+    Drone {
+        implementation: usize,
+        events: Sender<DroneEvent>,
+    },
+    Node {
+        node_type: CommonNodeType,
+        events: Sender<Box<dyn Event>>,
+    },
+}
+
+pub(crate) struct Supervisor {
+    pub(crate) policy: RestartPolicy,
+    pub(crate) max_restarts: usize,
+    pub(crate) network: NetworkHandle,
+    // the nodes are restarted with the neighbors they had in it
+    pub(crate) config: Config,
+    pub(crate) recipes: HashMap<NodeId, Recipe>,
+    // where each node reports its panic and restarts
+    pub(crate) reports: HashMap<NodeId, Sender<Box<dyn Event>>>,
+    pub(crate) panic_as_crash: bool,
+    pub(crate) handles: NodeHandles,
+    // restarted nodes are watched by the engine like the others
+    pub(crate) links: Links,
+    pub(crate) exits: Sender<(NodeId, Exit)>,
+}
+
+/// Stops the supervisor thread, before the nodes so none of them is restarted
+/// while the simulation stops
+pub(crate) struct SupervisorHandle {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl SupervisorHandle {
+    pub(crate) fn stop(self) -> std::thread::Result<()> {
+        drop(self.stop);
+        self.handle.join()
+    }
+}
+
+impl Supervisor {
+    pub(crate) fn spawn(self, exits: Receiver<(NodeId, Exit)>) -> SupervisorHandle {
+        let (stop, stopped) = crossbeam::channel::bounded::<()>(0);
+        let handle = std::thread::spawn(move || {
+            let mut restarts = HashMap::new();
+            loop {
+                select! {
+                    recv(exits) -> exit => {
+                        let Ok((id, exit)) = exit else { return };
+                        self.on_exit(id, exit, &mut restarts);
+                    }
+                    recv(stopped) -> _ => return,
+                }
+            }
+        });
+        SupervisorHandle { stop, handle }
+    }
+
+    fn on_exit(&self, id: NodeId, exit: Exit, restarts: &mut HashMap<NodeId, usize>) {
+        let restart = match self.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnPanic => exit == Exit::Panicked,
+            RestartPolicy::Always => true,
+        };
+        let count = restarts.entry(id).or_default();
+        if !restart || *count >= self.max_restarts {
+            return;
+        }
+        *count += 1;
+        let report: Box<dyn Event> = match self.restart(id) {
+            Ok(kind) => Box::new(NodeRestarted {
+                id,
+                kind,
+                restarts: *count,
+            }),
+            Err(error) => Box::new(NodeRestartFailed { id, error }),
+        };
+        if let Some(reports) = self.reports.get(&id) {
+            let _ = reports.send(report);
+        }
+    }
+
+    fn restart(&self, id: NodeId) -> Result<NodeType, ControlError> {
+        let config = &self.config;
+        let original = config
+            .drone
+            .iter()
+            .find(|d| d.id == id)
+            .map(utils::NodeType::Drone)
+            .or_else(|| {
+                config
+                    .client
+                    .iter()
+                    .find(|c| c.id == id)
+                    .map(utils::NodeType::Client)
+            })
+            .or_else(|| {
+                config
+                    .server
+                    .iter()
+                    .find(|s| s.id == id)
+                    .map(utils::NodeType::Server)
+            })
+            .ok_or(ControlError::UnknownNode(id))?;
+        let recipe = self.recipes.get(&id).ok_or(ControlError::UnknownNode(id))?;
+        let reports = self.reports.get(&id).ok_or(ControlError::UnknownNode(id))?;
+        let kind = match original {
+            utils::NodeType::Drone(_) => NodeType::Drone,
+            utils::NodeType::Client(_) => NodeType::Client,
+            utils::NodeType::Server(_) => NodeType::Server,
+        };
+
+        // packets and commands left in the old channels are lost
+        let packets = Channel::new();
+        let run: Box<dyn FnOnce() + Send> = match recipe {
+            Recipe::Drone {
+                implementation,
+                events,
+            } => {
+                let commands = Channel::new();
+                self.network.replace_drone_channels(
+                    id,
+                    commands.get_sender(),
+                    packets.get_sender(),
+                );
+                let neighbors = self.network.restore_node(&original)?;
+                let pdr = self
+                    .network
+                    .read_topology()
+                    .drone
+                    .iter()
+                    .find(|d| d.id == id)
+                    .map_or(0.0, |d| d.pdr);
+                let mut drone = generate_drone(
+                    *implementation,
+                    events,
+                    (
+                        id,
+                        commands.get_receiver(),
+                        packets.get_receiver(),
+                        neighbors,
+                        pdr,
+                    ),
+                );
+                Box::new(move || drone.run())
+            }
+            Recipe::Node { node_type, events } => {
+                let commands = Channel::new();
+                self.network
+                    .replace_node_channels(id, commands.get_sender(), packets.get_sender());
+                let neighbors = self.network.restore_node(&original)?;
+                let mut node = generate_node(
+                    *node_type,
+                    id,
+                    neighbors,
+                    packets.get_receiver(),
+                    commands.get_receiver(),
+                    events.clone(),
+                );
+                // the other nodes passed the start barrier long ago
+                Box::new(move || node.run(Arc::new(Barrier::new(1))))
+            }
+        };
+        let thread = spawn_isolated(
+            id,
+            kind,
+            self.links.watched(id, run),
+            reports.clone(),
+            self.panic_as_crash.then(|| self.network.clone()),
+            self.exits.clone(),
+        );
+        // the old thread sent its exit as its last step
+        let old = lock_handles(&self.handles).insert(id, thread);
+        if let Some(old) = old {
+            let _ = old.join();
+        }
+        Ok(kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ExecutionConfig;
+    use crate::utils::Channel;
+    use common::types::{Command, NodeCommand};
+    use wg_internal::config::{Client, Drone};
+    use wg_internal::controller::DroneCommand;
+
+    #[test]
+    fn test_panic_isolation() {
+        let events: Channel<Box<dyn Event>> = Channel::new();
+        let exits = Channel::new();
+        let thread = spawn_isolated(
+            7,
+            NodeType::Drone,
+            || panic!("Out of fuel"),
+            events.get_sender(),
+            None,
+            exits.get_sender(),
+        );
+        let panicked = thread.panicked.clone();
+        assert!(thread.handle.join().is_ok());
+        assert!(panicked.load(Ordering::SeqCst));
+        assert_eq!(exits.recv(), Ok((7, Exit::Panicked)));
+        let crashed = events.recv().unwrap().into_any().downcast::<NodeCrashed>();
+        assert_eq!(
+            *crashed.unwrap(),
+            NodeCrashed {
+                id: 7,
+                kind: NodeType::Drone,
+                message: "Out of fuel".to_string(),
+            }
+        );
+
+        spawn_isolated(
+            8,
+            NodeType::Client,
+            || {},
+            events.get_sender(),
+            None,
+            exits.get_sender(),
+        )
+        .join()
+        .unwrap();
+        assert!(events.get_receiver().try_recv().is_err());
+        assert_eq!(exits.recv(), Ok((8, Exit::Finished)));
+    }
+
+    #[test]
+    fn test_panic_as_crash() {
+        let drone_2 = Channel::new();
+        let client_4: Channel<Box<dyn Command>> = Channel::new();
+        let shutdown: Channel<()> = Channel::new();
+        let network = NetworkHandle::new(
+            HashMap::from([(1, Channel::new().get_sender()), (2, drone_2.get_sender())]),
+            HashMap::from([(4, (CommonNodeType::WebBrowser, client_4.get_sender()))]),
+            &HashMap::new(),
+            Links::new(shutdown.get_receiver(), None, &ExecutionConfig::default()),
+            Config {
+                drone: vec![
+                    Drone {
+                        id: 1,
+                        connected_node_ids: vec![2, 4],
+                        pdr: 0.0,
+                    },
+                    Drone {
+                        id: 2,
+                        connected_node_ids: vec![1],
+                        pdr: 0.0,
+                    },
+                ],
+                client: vec![Client {
+                    id: 4,
+                    connected_drone_ids: vec![1],
+                }],
+                server: Vec::new(),
+            },
+        );
+        let events: Channel<Box<dyn Event>> = Channel::new();
+        let exits = Channel::new();
+        let thread = spawn_isolated(
+            1,
+            NodeType::Drone,
+            || panic!("Out of fuel"),
+            events.get_sender(),
+            Some(network.clone()),
+            exits.get_sender(),
+        );
+        assert_eq!(thread.join().ok(), Some(Exit::Panicked));
+
+        assert!(matches!(drone_2.recv(), Ok(DroneCommand::RemoveSender(1))));
+        let command: Box<dyn Any> = client_4.recv().unwrap();
+        assert!(matches!(
+            command.downcast_ref::<NodeCommand>(),
+            Some(NodeCommand::RemoveSender(1))
+        ));
+        let topology = network.read_topology();
+        assert!(topology.drone.iter().all(|d| d.id != 1));
+        assert_eq!(topology.drone[0].connected_node_ids, Vec::<NodeId>::new());
+        assert_eq!(topology.client[0].connected_drone_ids, Vec::<NodeId>::new());
+        assert!(events.recv().unwrap().into_any().is::<NodeCrashed>());
+    }
+
+    #[test]
+    fn test_restart_failure() {
+        let shutdown: Channel<()> = Channel::new();
+        let links = Links::new(shutdown.get_receiver(), None, &ExecutionConfig::default());
+        let config = Config {
+            drone: Vec::new(),
+            client: Vec::new(),
+            server: Vec::new(),
+        };
+        let reports: Channel<Box<dyn Event>> = Channel::new();
+        let supervisor = Supervisor {
+            policy: RestartPolicy::OnPanic,
+            max_restarts: 1,
+            network: NetworkHandle::new(
+                HashMap::new(),
+                HashMap::new(),
+                &HashMap::new(),
+                links.clone(),
+                config.clone(),
+            ),
+            config,
+            recipes: HashMap::new(),
+            reports: HashMap::from([(9, reports.get_sender())]),
+            panic_as_crash: false,
+            handles: NodeHandles::default(),
+            links,
+            exits: Channel::new().get_sender(),
+        };
+        let mut restarts = HashMap::new();
+
+        supervisor.on_exit(9, Exit::Finished, &mut restarts);
+        assert!(reports.get_receiver().try_recv().is_err(), "Not a panic");
+        supervisor.on_exit(9, Exit::Panicked, &mut restarts);
+        let failed = reports
+            .recv()
+            .unwrap()
+            .into_any()
+            .downcast::<NodeRestartFailed>();
+        assert_eq!(
+            *failed.unwrap(),
+            NodeRestartFailed {
+                id: 9,
+                error: ControlError::UnknownNode(9),
+            }
+        );
+    }
+}
@@ -0,0 +1,526 @@
+//! Generators for the standard WG topologies. Every generator numbers the
+//! drones first, then the clients and the servers, and returns a [`Config`]
+//! that passes `validate_config`.
+
+use crate::errors::ConfigError;
+use crate::parser::Validate;
+use rand::rngs::StdRng;
+use rand::seq::{IndexedRandom, SliceRandom};
+use rand::{Rng, SeedableRng};
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use wg_internal::config::{Client, Config, Drone, Server};
+use wg_internal::network::NodeId;
+
+/// Number of clients and servers attached to a generated topology, and the
+/// PDR of its drones
+#[derive(Debug, Clone, Copy)]
+pub struct Endpoints {
+    pub clients: usize,
+    pub servers: usize,
+    pub pdr: f32,
+}
+
+/// Two parallel chains of `length` drones, with a rung between the drones at
+/// the same position. Clients attach at the start of the chains, servers at the end
+///
+/// # Errors
+/// Returns an error if `length` is 0 or the topology is not valid
+pub fn double_chain(length: usize, endpoints: Endpoints) -> Result<Config, ConfigError> {
+    if length == 0 {
+        return Err(ConfigError::InvalidConfig(
+            "A double chain needs at least one drone per chain".to_string(),
+        ));
+    }
+    let mut builder = Builder::new(endpoints.pdr);
+    let top = builder.drones(length)?;
+    let bottom = builder.drones(length)?;
+    for i in 0..length {
+        builder.connect(top[i], bottom[i]);
+        if i + 1 < length {
+            builder.connect(top[i], top[i + 1]);
+            builder.connect(bottom[i], bottom[i + 1]);
+        }
+    }
+    builder.clients(endpoints.clients, &[top[0], bottom[0]])?;
+    builder.servers(endpoints.servers, &[top[length - 1], bottom[length - 1]])?;
+    builder.build()
+}
+
+/// `drones` drones on a circle, each connected to the ones `step` positions
+/// away. `star(10, 3, ..)` is the WG decagram
+///
+/// # Errors
+/// Returns an error if `step` is 0, not smaller than `drones` or shares a
+/// divisor with it (the circle would split into separate rings), or the
+/// topology is not valid
+pub fn star(drones: usize, step: usize, endpoints: Endpoints) -> Result<Config, ConfigError> {
+    if step == 0 || step >= drones {
+        return Err(ConfigError::InvalidConfig(format!(
+            "A star of {drones} drones needs a step between 1 and {}",
+            drones.saturating_sub(1)
+        )));
+    }
+    if gcd(drones, step) != 1 {
+        return Err(ConfigError::InvalidConfig(format!(
+            "A star of {drones} drones with step {step} splits into {} rings",
+            gcd(drones, step)
+        )));
+    }
+    let mut builder = Builder::new(endpoints.pdr);
+    let ids = builder.drones(drones)?;
+    for (i, id) in ids.iter().enumerate() {
+        builder.connect(*id, ids[(i + step) % drones]);
+    }
+    builder.clients(endpoints.clients, &ids)?;
+    builder.servers(endpoints.servers, &ids)?;
+    builder.build()
+}
+
+/// Butterfly network of `stages` columns of `2^(stages - 1)` drones: drone `r`
+/// of column `s` is connected to drone `r` and drone `r ^ 2^s` of column `s + 1`.
+/// Clients attach to the first column, servers to the last one
+///
+/// # Errors
+/// Returns an error if `stages` is smaller than 2 or the topology is not valid
+pub fn butterfly(stages: u32, endpoints: Endpoints) -> Result<Config, ConfigError> {
+    if stages < 2 {
+        return Err(ConfigError::InvalidConfig(
+            "A butterfly needs at least two stages".to_string(),
+        ));
+    }
+    let rows = 1usize
+        .checked_shl(stages - 1)
+        .ok_or_else(|| ConfigError::InvalidConfig(format!("Too many stages: {stages}")))?;
+    let mut builder = Builder::new(endpoints.pdr);
+    let columns = (0..stages)
+        .map(|_| builder.drones(rows))
+        .collect::<Result<Vec<_>, _>>()?;
+    for (s, pair) in columns.windows(2).enumerate() {
+        for r in 0..rows {
+            builder.connect(pair[0][r], pair[1][r]);
+            builder.connect(pair[0][r], pair[1][r ^ (1 << s)]);
+        }
+    }
+    builder.clients(endpoints.clients, &columns[0])?;
+    builder.servers(endpoints.servers, &columns[columns.len() - 1])?;
+    builder.build()
+}
+
+/// Tree of drones with the given `depth` and `branching` factor. Clients attach
+/// to the leaves, servers to the root and its children
+///
+/// # Errors
+/// Returns an error if `depth` or `branching` is 0 or the topology is not valid
+pub fn tree(depth: u32, branching: usize, endpoints: Endpoints) -> Result<Config, ConfigError> {
+    if depth == 0 || branching == 0 {
+        return Err(ConfigError::InvalidConfig(
+            "A tree needs a depth and a branching factor of at least one".to_string(),
+        ));
+    }
+    let mut builder = Builder::new(endpoints.pdr);
+    let root = builder.drones(1)?;
+    let mut levels = vec![root];
+    for _ in 0..depth {
+        let parents = &levels[levels.len() - 1];
+        let children = builder.drones(parents.len() * branching)?;
+        for (i, child) in children.iter().enumerate() {
+            builder.connect(parents[i / branching], *child);
+        }
+        levels.push(children);
+    }
+    let core: Vec<NodeId> = levels[0].iter().chain(&levels[1]).copied().collect();
+    builder.clients(endpoints.clients, &levels[levels.len() - 1])?;
+    builder.servers(endpoints.servers, &core)?;
+    builder.build()
+}
+
+/// `triangles` fully connected triangles of drones, each joined to the next one.
+/// Clients attach to the first triangle, servers to the last one
+///
+/// # Errors
+/// Returns an error if `triangles` is 0 or the topology is not valid
+pub fn subnet_triangles(triangles: usize, endpoints: Endpoints) -> Result<Config, ConfigError> {
+    if triangles == 0 {
+        return Err(ConfigError::InvalidConfig(
+            "At least one triangle is needed".to_string(),
+        ));
+    }
+    let mut builder = Builder::new(endpoints.pdr);
+    let subnets = (0..triangles)
+        .map(|_| builder.drones(3))
+        .collect::<Result<Vec<_>, _>>()?;
+    for (i, subnet) in subnets.iter().enumerate() {
+        builder.connect(subnet[0], subnet[1]);
+        builder.connect(subnet[1], subnet[2]);
+        builder.connect(subnet[2], subnet[0]);
+        if let Some(next) = subnets.get(i + 1) {
+            builder.connect(subnet[2], next[0]);
+        }
+    }
+    builder.clients(endpoints.clients, &subnets[0])?;
+    builder.servers(endpoints.servers, &subnets[triangles - 1])?;
+    builder.build()
+}
+
+/// Random connected topology: the drones form a random spanning tree, with
+/// extra links added until their average degree among drones reaches
+/// `avg_degree`. Every client is attached to one or two drones, every server to
+/// two or three, and each drone gets a PDR drawn from `pdr_range`. The same
+/// seed always produces the same config
+///
+/// # Errors
+/// Returns an error if `pdr_range` is not inside `[0, 1]`, there are not enough
+/// drones for the clients and servers, or the node ids do not fit in a `NodeId`
+pub fn random_topology(
+    seed: u64,
+    drones: usize,
+    clients: usize,
+    servers: usize,
+    avg_degree: f32,
+    pdr_range: RangeInclusive<f32>,
+) -> Result<Config, ConfigError> {
+    if pdr_range.is_empty() || *pdr_range.start() < 0.0 || *pdr_range.end() > 1.0 {
+        return Err(ConfigError::InvalidPdrValue);
+    }
+    if (clients > 0 && drones == 0) || (servers > 0 && drones < 2) {
+        return Err(ConfigError::InvalidConfig(format!(
+            "{drones} drones are not enough for {clients} clients and {servers} servers"
+        )));
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut builder = Builder::new(0.0);
+    let ids = builder.drones(drones)?;
+    for drone in &mut builder.config.drone {
+        drone.pdr = rng.random_range(pdr_range.clone());
+    }
+
+    // spanning tree over a random order of the drones, so that they are connected
+    let mut order = ids.clone();
+    order.shuffle(&mut rng);
+    let mut links = HashSet::new();
+    for i in 1..order.len() {
+        let parent = order[rng.random_range(0..i)];
+        links.insert((parent.min(order[i]), parent.max(order[i])));
+    }
+    let max_links = drones * drones.saturating_sub(1) / 2;
+    // truncation is fine, the target only needs to be approximated
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let target = ((avg_degree.max(0.0) * drones as f32 / 2.0).round() as usize).min(max_links);
+    while links.len() < target {
+        let pair: Vec<NodeId> = ids.choose_multiple(&mut rng, 2).copied().collect();
+        links.insert((pair[0].min(pair[1]), pair[0].max(pair[1])));
+    }
+    let mut links: Vec<(NodeId, NodeId)> = links.into_iter().collect();
+    links.sort_unstable();
+    for (a, b) in links {
+        builder.connect(a, b);
+    }
+
+    for _ in 0..clients {
+        let count = rng.random_range(1..=drones.min(2));
+        builder.client(ids.choose_multiple(&mut rng, count).copied().collect())?;
+    }
+    for _ in 0..servers {
+        let count = rng.random_range(2..=drones.min(3));
+        builder.server(ids.choose_multiple(&mut rng, count).copied().collect())?;
+    }
+    builder.build()
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+struct Builder {
+    config: Config,
+    next_id: usize,
+    pdr: f32,
+}
+
+impl Builder {
+    fn new(pdr: f32) -> Self {
+        Self {
+            config: Config {
+                drone: Vec::new(),
+                client: Vec::new(),
+                server: Vec::new(),
+            },
+            next_id: 1,
+            pdr,
+        }
+    }
+
+    fn next_ids(&mut self, count: usize) -> Result<Vec<NodeId>, ConfigError> {
+        let ids = (self.next_id..self.next_id + count)
+            .map(|id| {
+                NodeId::try_from(id)
+                    .map_err(|_| ConfigError::InvalidConfig(format!("Too many nodes: {id}")))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.next_id += count;
+        Ok(ids)
+    }
+
+    fn drones(&mut self, count: usize) -> Result<Vec<NodeId>, ConfigError> {
+        let ids = self.next_ids(count)?;
+        self.config.drone.extend(ids.iter().map(|id| Drone {
+            id: *id,
+            connected_node_ids: Vec::new(),
+            pdr: self.pdr,
+        }));
+        Ok(ids)
+    }
+
+    fn connect(&mut self, a: NodeId, b: NodeId) {
+        for (from, to) in [(a, b), (b, a)] {
+            if let Some(drone) = self.config.drone.iter_mut().find(|d| d.id == from)
+                && !drone.connected_node_ids.contains(&to)
+            {
+                drone.connected_node_ids.push(to);
+            }
+        }
+    }
+
+    fn client(&mut self, attached: Vec<NodeId>) -> Result<(), ConfigError> {
+        let id = self.next_ids(1)?[0];
+        for drone in &attached {
+            self.connect(id, *drone);
+        }
+        self.config.client.push(Client {
+            id,
+            connected_drone_ids: attached,
+        });
+        Ok(())
+    }
+
+    fn server(&mut self, attached: Vec<NodeId>) -> Result<(), ConfigError> {
+        let id = self.next_ids(1)?[0];
+        for drone in &attached {
+            self.connect(id, *drone);
+        }
+        self.config.server.push(Server {
+            id,
+            connected_drone_ids: attached,
+        });
+        Ok(())
+    }
+
+    // client `k` attaches to `drones[k % drones.len()]`
+    fn clients(&mut self, count: usize, drones: &[NodeId]) -> Result<(), ConfigError> {
+        for k in 0..count {
+            self.client(vec![drones[k % drones.len()]])?;
+        }
+        Ok(())
+    }
+
+    // server `k` attaches to two consecutive drones, starting at `drones[2k]`
+    fn servers(&mut self, count: usize, drones: &[NodeId]) -> Result<(), ConfigError> {
+        if count > 0 && drones.len() < 2 {
+            return Err(ConfigError::InvalidConfig(
+                "Servers need at least two drones to attach to".to_string(),
+            ));
+        }
+        for k in 0..count {
+            self.server(vec![
+                drones[(2 * k) % drones.len()],
+                drones[(2 * k + 1) % drones.len()],
+            ])?;
+        }
+        Ok(())
+    }
+
+    // every drone can be reached from the first one, so every endpoint can
+    // reach every other one
+    fn drones_connected(&self) -> bool {
+        let Some(first) = self.config.drone.first() else {
+            return true;
+        };
+        let mut seen = HashSet::from([first.id]);
+        let mut stack = vec![first.id];
+        while let Some(id) = stack.pop() {
+            let drone = self.config.drone.iter().find(|d| d.id == id);
+            for neighbor in drone.into_iter().flat_map(|d| &d.connected_node_ids) {
+                if self.config.drone.iter().any(|d| d.id == *neighbor) && seen.insert(*neighbor) {
+                    stack.push(*neighbor);
+                }
+            }
+        }
+        seen.len() == self.config.drone.len()
+    }
+
+    fn build(self) -> Result<Config, ConfigError> {
+        self.config.validate_config()?;
+        if !self.drones_connected() {
+            return Err(ConfigError::InvalidConfig(
+                "The generated drones are not connected".to_string(),
+            ));
+        }
+        Ok(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parse;
+
+    const ENDPOINTS: Endpoints = Endpoints {
+        clients: 2,
+        servers: 2,
+        pdr: 0.05,
+    };
+
+    // every node with its sorted neighbors, to compare configs
+    fn adjacency(config: &Config) -> Vec<(NodeId, Vec<NodeId>)> {
+        let mut nodes: Vec<(NodeId, Vec<NodeId>)> = config
+            .drone
+            .iter()
+            .map(|d| (d.id, d.connected_node_ids.clone()))
+            .chain(
+                config
+                    .client
+                    .iter()
+                    .map(|c| (c.id, c.connected_drone_ids.clone())),
+            )
+            .chain(
+                config
+                    .server
+                    .iter()
+                    .map(|s| (s.id, s.connected_drone_ids.clone())),
+            )
+            .collect();
+        for (_, ids) in &mut nodes {
+            ids.sort_unstable();
+        }
+        nodes.sort_unstable();
+        nodes
+    }
+
+    #[test]
+    fn test_shipped_topologies() {
+        let generated = [
+            ("double_chain", double_chain(5, ENDPOINTS)),
+            ("decagram", star(10, 3, ENDPOINTS)),
+            ("butterfly", butterfly(3, ENDPOINTS)),
+            ("tree", tree(2, 2, ENDPOINTS)),
+            ("subnet_triangles", subnet_triangles(3, ENDPOINTS)),
+        ];
+        for (name, config) in generated {
+            let config = config.unwrap();
+            let shipped = Config::parse_config(&format!("./config/{name}.toml")).unwrap();
+            shipped.validate_config().unwrap();
+            assert_eq!(adjacency(&config), adjacency(&shipped), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_generated_shapes() {
+        let decagram = star(10, 3, ENDPOINTS).unwrap();
+        assert_eq!(decagram.drone.len(), 10);
+        assert!(
+            decagram
+                .drone
+                .iter()
+                .all(|d| d.connected_node_ids.len() >= 2)
+        );
+
+        let tree = tree(3, 2, ENDPOINTS).unwrap();
+        assert_eq!(tree.drone.len(), 15);
+        assert_eq!(tree.client.len(), 2);
+        assert!(tree.server.iter().all(|s| s.connected_drone_ids.len() == 2));
+
+        let triangles = subnet_triangles(
+            1,
+            Endpoints {
+                pdr: 0.5,
+                ..ENDPOINTS
+            },
+        )
+        .unwrap();
+        assert!(
+            triangles
+                .drone
+                .iter()
+                .all(|d| (d.pdr - 0.5).abs() < f32::EPSILON)
+        );
+
+        assert!(double_chain(0, ENDPOINTS).is_err());
+        assert!(star(4, 4, ENDPOINTS).is_err());
+        assert!(
+            star(6, 2, ENDPOINTS).is_err(),
+            "Two separate rings of three drones"
+        );
+        assert!(star(7, 2, ENDPOINTS).is_ok_and(|star| is_connected(&star)));
+        assert!(butterfly(1, ENDPOINTS).is_err());
+        let crowded = Endpoints {
+            clients: 10,
+            ..ENDPOINTS
+        };
+        assert!(
+            star(250, 1, crowded).is_err(),
+            "Node ids must fit in a NodeId"
+        );
+        let unreliable = Endpoints {
+            pdr: 1.5,
+            ..ENDPOINTS
+        };
+        assert!(
+            double_chain(1, unreliable).is_err(),
+            "Generated configs must be validated"
+        );
+    }
+
+    // every node can be reached from the first drone
+    fn is_connected(config: &Config) -> bool {
+        let nodes = adjacency(config);
+        let mut seen = HashSet::from([nodes[0].0]);
+        let mut stack = vec![nodes[0].0];
+        while let Some(id) = stack.pop() {
+            let (_, neighbors) = nodes.iter().find(|(n, _)| *n == id).unwrap();
+            for neighbor in neighbors {
+                if seen.insert(*neighbor) {
+                    stack.push(*neighbor);
+                }
+            }
+        }
+        seen.len() == nodes.len()
+    }
+
+    #[test]
+    fn test_random_topology() {
+        for seed in 0..200 {
+            let drones = 2 + (seed as usize % 20);
+            let config = random_topology(seed, drones, 3, 2, 3.0, 0.0..=0.3).unwrap();
+            config.validate_config().unwrap();
+            assert!(is_connected(&config), "Seed {seed} is not connected");
+            assert_eq!(config.drone.len(), drones);
+            assert!(config.drone.iter().all(|d| (0.0..=0.3).contains(&d.pdr)));
+            assert!(
+                config
+                    .client
+                    .iter()
+                    .all(|c| (1..=2).contains(&c.connected_drone_ids.len()))
+            );
+            assert!(
+                config
+                    .server
+                    .iter()
+                    .all(|s| s.connected_drone_ids.len() >= 2)
+            );
+        }
+
+        let first = random_topology(7, 12, 2, 2, 4.0, 0.1..=0.1).unwrap();
+        let again = random_topology(7, 12, 2, 2, 4.0, 0.1..=0.1).unwrap();
+        assert_eq!(adjacency(&first), adjacency(&again));
+        let degrees: usize = first
+            .drone
+            .iter()
+            .map(|d| d.connected_node_ids.iter().filter(|id| **id <= 12).count())
+            .sum();
+        assert_eq!(degrees, 48, "12 drones of average degree 4");
+
+        assert!(random_topology(1, 1, 1, 1, 2.0, 0.0..=0.1).is_err());
+        assert!(random_topology(1, 5, 1, 1, 2.0, 0.5..=1.5).is_err());
+    }
+}
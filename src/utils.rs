@@ -3,7 +3,10 @@
 #![allow(unused_variables)]
 
 use ap2024_unitn_cppenjoyers_drone::CppEnjoyersDrone;
-use common::types::NodeCommand;
+use client::chat_client::ChatClient;
+use client::web_browser::WebBrowser;
+use common::Processor;
+use common::types::{Command, Event, NodeCommand, NodeType as CommonNodeType, WebCommand};
 use crossbeam::channel::{Receiver, Sender, unbounded};
 use d_r_o_n_e_drone::MyDrone as DroneDrone;
 use dr_ones::Drone as DrOnesDrone;
@@ -14,9 +17,13 @@ use rustafarian_drone::RustafarianDrone;
 use rustbusters_drone::RustBustersDrone;
 use rusteze_drone::RustezeDrone;
 use rusty_drones::RustyDrone;
+use serde::{Deserialize, Deserializer};
+use server::{ChatServer, MediaServer, TextServer};
+use std::any::Any;
 use std::collections::HashMap;
+use std::time::Duration;
 use wg_2024_rust::drone::RustDrone;
-use wg_internal::config::{Client, Drone, Server};
+use wg_internal::config::{Client, Config, Drone, Server};
 use wg_internal::controller::{DroneCommand, DroneEvent};
 use wg_internal::drone::Drone as DroneTrait;
 use wg_internal::network::NodeId;
@@ -54,7 +61,7 @@ impl<'a> NodeType<'a> {
 }
 
 macro_rules! drone_factories {
-    ( $( $variant:ident ),* $(,)? ) => {
+    ( $( $variant:ident: $enabled:literal ),* $(,)? ) => {
         paste::paste! {
             // one factory function per drone type
             $(
@@ -83,23 +90,66 @@ macro_rules! drone_factories {
                     [<$variant:snake _factory>],
                 )*
             ];
+
+            // name of the drone type built by each factory
+            static NAMES: &[&str] = &[
+                $(
+                    stringify!($variant),
+                )*
+            ];
+
+            // whether each drone type takes turns for the drones without an
+            // `implementation`: the ones that never panic in the conformance
+            // checks, `test_conformance_matrix` keeps the flags in line
+            static ENABLED: &[bool] = &[
+                $(
+                    $enabled,
+                )*
+            ];
         }
     };
 }
 
+// every known implementation; only the enabled ones are picked by default, a
+// config can still name the others. `network-initializer conformance` checks
+// how each one behaves
 drone_factories!(
-    CppEnjoyersDrone,
-    // DroneDrone, // panick
-    DrOnesDrone, // --> termina
-    // LockheedRustin, // --> panick
-    //NullPointerDrone, // panick
-    // RustafarianDrone, // panick
-    RustBustersDrone, // --> termina
-    RustezeDrone, //  --> non termina
-    RustyDrone, // --> non termina
-    RustDrone, // --> non termina
+    CppEnjoyersDrone: true,
+    DroneDrone: false,
+    DrOnesDrone: true,
+    LockheedRustin: false,
+    NullPointerDrone: false,
+    RustafarianDrone: false,
+    RustBustersDrone: true,
+    RustezeDrone: true,
+    RustyDrone: true,
+    RustDrone: true,
+    RustDoIt: true,
 );
 
+pub(crate) fn drone_implementation_names() -> &'static [&'static str] {
+    NAMES
+}
+
+pub(crate) fn drone_implementation_enabled(i: usize) -> bool {
+    ENABLED[i]
+}
+
+pub(crate) fn drone_implementation_name(i: usize) -> &'static str {
+    NAMES[i]
+}
+
+/// Index of the implementation of the `i`-th drone without an `implementation`:
+/// the enabled ones take turns
+pub(crate) fn default_drone_implementation(i: usize) -> usize {
+    let enabled: Vec<usize> = (0..NAMES.len()).filter(|j| ENABLED[*j]).collect();
+    enabled[i % enabled.len()]
+}
+
+pub(crate) fn drone_implementation_index(name: &str) -> Option<usize> {
+    NAMES.iter().position(|n| n.eq_ignore_ascii_case(name))
+}
+
 pub(crate) fn generate_drone(
     i: usize,
     controller_send: &Sender<DroneEvent>,
@@ -116,6 +166,123 @@ pub(crate) fn generate_drone(
     )
 }
 
+/// Type of the client at `index` in the config: web browsers and chat clients
+/// alternate
+pub(crate) fn client_type(index: usize) -> CommonNodeType {
+    if index.is_multiple_of(2) {
+        CommonNodeType::WebBrowser
+    } else {
+        CommonNodeType::ChatClient
+    }
+}
+
+/// Type of the server at `index` in the config: text, media and chat servers
+/// take turns
+pub(crate) fn server_type(index: usize) -> CommonNodeType {
+    match index % 3 {
+        0 => CommonNodeType::TextServer,
+        1 => CommonNodeType::MediaServer,
+        _ => CommonNodeType::ChatServer,
+    }
+}
+
+/// Type of every client and server of `config`
+pub(crate) fn endpoint_types(config: &Config) -> HashMap<NodeId, CommonNodeType> {
+    let clients = config
+        .client
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (c.id, client_type(i)));
+    let servers = config
+        .server
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.id, server_type(i)));
+    clients.chain(servers).collect()
+}
+
+/// Whether a node of type `node_type` handles `command`: web commands that
+/// query files go to web browsers, adding a text file to text servers, and
+/// node commands to any client or server
+///
+/// # Errors
+/// Returns a message naming the node types that handle `command`
+pub(crate) fn check_command(
+    node_type: CommonNodeType,
+    command: &dyn Command,
+) -> Result<(), String> {
+    let command: &dyn Any = command;
+    let expected = match command.downcast_ref::<WebCommand>() {
+        Some(WebCommand::AddTextFileFromPath(_)) => &[CommonNodeType::TextServer][..],
+        Some(
+            WebCommand::GetTextFilesList
+            | WebCommand::QueryTextFilesList
+            | WebCommand::GetCachedFiles,
+        ) => &[CommonNodeType::WebBrowser][..],
+        None => return Ok(()),
+    };
+    if expected.contains(&node_type) {
+        Ok(())
+    } else {
+        Err(format!(
+            "a {node_type:?} does not handle it, only a {}",
+            expected
+                .iter()
+                .map(|t| format!("{t:?}"))
+                .collect::<Vec<_>>()
+                .join(" or ")
+        ))
+    }
+}
+
+/// Client or server of the given type
+pub(crate) fn generate_node(
+    node_type: CommonNodeType,
+    id: NodeId,
+    neighbors: HashMap<NodeId, Sender<Packet>>,
+    packet_recv: Receiver<Packet>,
+    command_recv: Receiver<Box<dyn Command>>,
+    event_send: Sender<Box<dyn Event>>,
+) -> Box<dyn Processor> {
+    match node_type {
+        CommonNodeType::WebBrowser => Box::new(WebBrowser::new(
+            id,
+            neighbors,
+            packet_recv,
+            command_recv,
+            event_send,
+        )),
+        CommonNodeType::ChatClient => Box::new(ChatClient::new(
+            id,
+            neighbors,
+            packet_recv,
+            command_recv,
+            event_send,
+        )),
+        CommonNodeType::TextServer => Box::new(TextServer::new(
+            id,
+            neighbors,
+            packet_recv,
+            command_recv,
+            event_send,
+        )),
+        CommonNodeType::MediaServer => Box::new(MediaServer::new(
+            id,
+            neighbors,
+            packet_recv,
+            command_recv,
+            event_send,
+        )),
+        CommonNodeType::ChatServer => Box::new(ChatServer::new(
+            id,
+            neighbors,
+            packet_recv,
+            command_recv,
+            event_send,
+        )),
+    }
+}
+
 #[derive(Clone)]
 pub struct Channel<T> {
     pub(crate) sender: Sender<T>,
@@ -145,6 +312,62 @@ impl<T> Channel<T> {
     }
 }
 
+/// Random streams derived from the simulation seed
+pub(crate) const LINK_STREAM: u64 = 1;
+pub(crate) const FAULT_STREAM: u64 = 2;
+
+/// Seed of an independent random stream, e.g. one per link: the simulation
+/// seed and the parts naming the stream are mixed with SplitMix64, so close
+/// seeds or streams do not give correlated generators
+pub(crate) fn derive_seed(seed: u64, stream: &[u64]) -> u64 {
+    stream.iter().fold(splitmix64(seed), |acc, part| {
+        splitmix64(acc ^ splitmix64(*part))
+    })
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// The message a thread panicked with
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(ToString::to_string)
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// Parses durations such as `"500ms"`, `"2s"`, `"1.5s"` or `"1m"`
+pub(crate) fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: f64 = amount
+        .parse()
+        .map_err(|_| format!("Invalid duration: {value}"))?;
+    let seconds = match unit.trim() {
+        "ms" => amount / 1000.0,
+        "s" | "" => amount,
+        "m" => amount * 60.0,
+        _ => return Err(format!("Invalid duration unit: {value}")),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("Invalid duration {value}: {e}"))
+}
+
+pub(crate) fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    parse_duration(&value).map_err(serde::de::Error::custom)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -187,4 +410,14 @@ mod tests {
         //     assert_eq!(expected_type, i % FACTORIES.len());
         // }
     }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("1m"), Ok(Duration::from_secs(60)));
+        assert!(parse_duration("fast").is_err());
+        assert!(parse_duration("3h").is_err());
+    }
 }